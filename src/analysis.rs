@@ -0,0 +1,379 @@
+//! How much damage a symbol can take before it stops decoding.
+//!
+//! Every block can correct up to half as many wrong codewords as it has
+//! error correction codewords, less those which the smallest symbols set
+//! aside for misdecode protection (see `ec::misdecode_protection`). Damaged
+//! modules are traced back to the codewords they belong to with a
+//! `ProvenanceMap`, and a damaged codeword counts once no matter how many of
+//! its 8 modules are hit. Damage is assumed to be undetected, i.e. to cause
+//! errors rather than erasures, as is the case for a logo or sticker on top
+//! of the symbol. The assessment is conservative: a covered codeword may keep
+//! its value by chance.
+
+use image::{Rgb, RgbImage};
+
+use crate::bch::MAX_CORRECTABLE_DISTANCE;
+use crate::canvas::{FORMAT_INFO_COORDS_QR_MAIN, FORMAT_INFO_COORDS_QR_SIDE};
+use crate::cast::As;
+use crate::ec;
+use crate::provenance::{CodewordKind, ModuleRole, ProvenanceMap};
+use crate::render::Renderer;
+use crate::types::{Color, EcLevel, QrResult, Version};
+
+/// A rectangle of modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i16,
+    pub y: i16,
+    pub width: i16,
+    pub height: i16,
+}
+
+impl Rect {
+    pub const fn contains(&self, x: i16, y: i16) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// The effect of some damage on a symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamageReport {
+    /// The number of damaged codewords in each block, in the order defined
+    /// by `DATA_BYTES_PER_BLOCK`.
+    pub damaged_codewords: Vec<usize>,
+
+    /// The number of wrong codewords every block can correct.
+    pub correctable_codewords: usize,
+
+    /// Whether at least one copy of the format information has few enough
+    /// damaged modules for its BCH code to correct.
+    pub format_info_readable: bool,
+
+    /// The number of damaged modules of finder, timing and alignment
+    /// patterns and the like. Decoding a clean module matrix does not need
+    /// them, but locating the symbol in an image does.
+    pub damaged_function_modules: usize,
+
+    /// How many of those belong to the finder patterns, without which
+    /// scanners cannot find the symbol at all.
+    pub damaged_finder_modules: usize,
+}
+
+impl DamageReport {
+    /// Whether a matrix with this damage still decodes.
+    pub fn is_decodable(&self) -> bool {
+        self.margin().is_some()
+    }
+
+    /// The number of further codewords the most damaged block can lose, or
+    /// `None` if the symbol no longer decodes.
+    pub fn margin(&self) -> Option<usize> {
+        let worst = self.damaged_codewords.iter().copied().max().unwrap_or(0);
+        (self.format_info_readable && worst <= self.correctable_codewords)
+            .then(|| self.correctable_codewords - worst)
+    }
+}
+
+/// The error correction capacity of a symbol, and the modules it protects.
+#[derive(Debug, Clone)]
+pub struct EcBudget {
+    map: ProvenanceMap,
+
+    /// The number of data codewords in each block.
+    pub data_lengths: Vec<usize>,
+
+    /// The number of error correction codewords in every block.
+    pub ec_length: usize,
+
+    /// How many of those are set aside for misdecode protection.
+    pub misdecode_protection: usize,
+}
+
+impl EcBudget {
+    pub fn new(version: Version, ec_level: EcLevel) -> QrResult<Self> {
+        let (data_lengths, ec_length) = ec::block_layout(version, ec_level)?;
+        Ok(Self {
+            map: ProvenanceMap::new(version, ec_level)?,
+            data_lengths,
+            ec_length,
+            misdecode_protection: ec::misdecode_protection(version, ec_level),
+        })
+    }
+
+    pub const fn width(&self) -> i16 {
+        self.map.width()
+    }
+
+    /// The number of wrong codewords every block can correct.
+    pub const fn correctable_errors(&self) -> usize {
+        self.correctable_erasures() / 2
+    }
+
+    /// The number of codewords every block can recover if their positions
+    /// are known, see `decode::decode_partial_matrix`.
+    pub const fn correctable_erasures(&self) -> usize {
+        self.ec_length - self.misdecode_protection
+    }
+
+    /// Assesses damage to the modules for which `damaged` is `true`, given
+    /// in left-to-right, then top-to-bottom order.
+    pub fn assess(&self, damaged: &[bool]) -> DamageReport {
+        let width = self.width();
+        self.assess_with(|x, y| damaged[(y * width + x).as_usize()])
+    }
+
+    /// Assesses damage to all modules inside `rect`.
+    pub fn assess_rect(&self, rect: Rect) -> DamageReport {
+        self.assess_with(|x, y| rect.contains(x, y))
+    }
+
+    fn assess_with<F: Fn(i16, i16) -> bool>(&self, damaged: F) -> DamageReport {
+        let width = self.width();
+        let mut codewords = self
+            .data_lengths
+            .iter()
+            .map(|len| vec![false; len + self.ec_length])
+            .collect::<Vec<_>>();
+        let mut damaged_function_modules = 0;
+        let mut damaged_finder_modules = 0;
+        for y in 0..width {
+            for x in 0..width {
+                if !damaged(x, y) {
+                    continue;
+                }
+                match self.map.get(x, y) {
+                    ModuleRole::Codeword {
+                        kind,
+                        block,
+                        index,
+                        ..
+                    } => {
+                        let offset = match kind {
+                            CodewordKind::Data => 0,
+                            CodewordKind::ErrorCorrection => self.data_lengths[block],
+                        };
+                        codewords[block][offset + index] = true;
+                    }
+                    ModuleRole::Remainder => {}
+                    role => {
+                        damaged_function_modules += 1;
+                        damaged_finder_modules += usize::from(role == ModuleRole::Finder);
+                    }
+                }
+            }
+        }
+
+        DamageReport {
+            damaged_codewords: codewords
+                .iter()
+                .map(|block| block.iter().filter(|d| **d).count())
+                .collect(),
+            correctable_codewords: self.correctable_errors(),
+            format_info_readable: format_info_readable(width, damaged),
+            damaged_function_modules,
+            damaged_finder_modules,
+        }
+    }
+
+    /// For every module, the share of the error correction budget used up by
+    /// a `size × size` square of damage centred on it (or just below and to
+    /// the right of it, for even sizes): the number of damaged codewords in
+    /// the worst block divided by the number it can correct. Values above 1
+    /// mean the symbol no longer decodes. Squares hiding both copies of the
+    /// format information, or part of a finder pattern, get `f64::INFINITY`.
+    pub fn heat_map(&self, size: i16) -> Vec<f64> {
+        let width = self.width();
+        let correctable = self.correctable_errors().max(1) as f64;
+
+        // The block and codeword (numbered across all blocks) of every module,
+        // and whether it is part of a finder pattern.
+        let mut block_starts = Vec::with_capacity(self.data_lengths.len());
+        let mut total = 0;
+        for len in &self.data_lengths {
+            block_starts.push(total);
+            total += len + self.ec_length;
+        }
+        let modules = (0..width)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| match self.map.get(x, y) {
+                ModuleRole::Codeword {
+                    kind,
+                    block,
+                    index,
+                    ..
+                } => {
+                    let offset = match kind {
+                        CodewordKind::Data => 0,
+                        CodewordKind::ErrorCorrection => self.data_lengths[block],
+                    };
+                    (Some((block, block_starts[block] + offset + index)), false)
+                }
+                role => (None, role == ModuleRole::Finder),
+            })
+            .collect::<Vec<_>>();
+
+        // The square a codeword was last counted in, so that it counts once.
+        let mut counted_in = vec![usize::MAX; total];
+        let mut damaged_codewords = vec![0; self.data_lengths.len()];
+        (0..modules.len())
+            .map(|i| {
+                let rect = Rect {
+                    x: (i % width.as_usize()).as_i16() - (size - 1) / 2,
+                    y: (i / width.as_usize()).as_i16() - (size - 1) / 2,
+                    width: size,
+                    height: size,
+                };
+                if !format_info_readable(width, |x, y| rect.contains(x, y)) {
+                    return f64::INFINITY;
+                }
+                damaged_codewords.fill(0);
+                for y in rect.y.max(0)..(rect.y + size).min(width) {
+                    for x in rect.x.max(0)..(rect.x + size).min(width) {
+                        match modules[(y * width + x).as_usize()] {
+                            (_, true) => return f64::INFINITY,
+                            (Some((block, codeword)), _) if counted_in[codeword] != i => {
+                                counted_in[codeword] = i;
+                                damaged_codewords[block] += 1;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                let worst = damaged_codewords.iter().copied().max().unwrap_or(0);
+                worst as f64 / correctable
+            })
+            .collect()
+    }
+
+    /// Renders a heat map from `heat_map`: green where damage is cheap,
+    /// through yellow to red where it uses up the whole budget, and dark grey
+    /// where the symbol no longer decodes.
+    pub fn render_heat_map(&self, values: &[f64], module_size: u32) -> RgbImage {
+        let width = self.width().as_usize();
+        let colors = vec![Color::Light; width * width];
+        Renderer::<Rgb<u8>>::new(&colors, width, 4)
+            .module_dimensions(module_size, module_size)
+            .build_with(|i, _| heat_color(values[i]))
+    }
+}
+
+/// Whether at least one copy of the format information of a symbol `width`
+/// modules wide has few enough modules for which `damaged` is `true` for its
+/// BCH code to correct.
+fn format_info_readable<F: Fn(i16, i16) -> bool>(width: i16, damaged: F) -> bool {
+    let normalize = |c: i16| if c < 0 { c + width } else { c };
+    [&FORMAT_INFO_COORDS_QR_MAIN, &FORMAT_INFO_COORDS_QR_SIDE]
+        .iter()
+        .any(|coords| {
+            let hits = coords.iter().filter(|(x, y)| damaged(normalize(*x), normalize(*y)));
+            hits.count().as_u32() <= MAX_CORRECTABLE_DISTANCE
+        })
+}
+
+/// Maps a share of the budget to a colour, see `EcBudget::render_heat_map`.
+fn heat_color(value: f64) -> Rgb<u8> {
+    if value > 1.0 {
+        return Rgb([60, 60, 60]);
+    }
+    let channel = |v: f64| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Rgb([channel(value * 2.0), channel((1.0 - value) * 2.0), 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode_matrix;
+    use crate::qr_code::QrCode;
+
+    #[test]
+    fn test_capacity() {
+        // 1-L has 7 error correction codewords, 3 of them for misdecode
+        // protection.
+        let budget = EcBudget::new(Version::Normal(1), EcLevel::L).unwrap();
+        assert_eq!((budget.ec_length, budget.misdecode_protection), (7, 3));
+        assert_eq!(budget.correctable_errors(), 2);
+        assert_eq!(budget.correctable_erasures(), 4);
+
+        let budget = EcBudget::new(Version::Normal(5), EcLevel::Q).unwrap();
+        assert_eq!(budget.data_lengths, [15, 15, 16, 16]);
+        assert_eq!(budget.correctable_errors(), 9);
+    }
+
+    #[test]
+    fn test_assess_rect() {
+        let budget = EcBudget::new(Version::Normal(1), EcLevel::L).unwrap();
+        // The bottom right module belongs to the first data codeword.
+        let report = budget.assess_rect(Rect { x: 20, y: 20, width: 1, height: 1 });
+        assert_eq!(report.damaged_codewords, [1]);
+        assert_eq!(report.margin(), Some(1));
+        assert_eq!(report.damaged_function_modules, 0);
+
+        // The top left finder pattern with its separator and format info.
+        let report = budget.assess_rect(Rect { x: 0, y: 0, width: 9, height: 9 });
+        assert_eq!(report.damaged_codewords, [0]);
+        assert_eq!(report.damaged_finder_modules, 49);
+        assert!(report.format_info_readable);
+        assert!(report.is_decodable());
+
+        // Both copies of the format information.
+        let report = budget.assess_rect(Rect { x: 0, y: 0, width: 21, height: 9 });
+        assert!(!report.format_info_readable);
+        assert!(!report.is_decodable());
+    }
+
+    #[test]
+    fn test_assess_agrees_with_decoder() {
+        let cases = [("HELLO", EcLevel::L), ("decodable", EcLevel::M), ("1234", EcLevel::H)];
+        for (data, ec_level) in cases {
+            let code = QrCode::with_error_correction_level(data, ec_level).unwrap();
+            let width = code.width();
+            let budget = EcBudget::new(code.version(), ec_level).unwrap();
+            let colors = code.to_colors();
+            for size in 1..5 {
+                for i in 0..width * width {
+                    let (x, y) = ((i % width).as_i16(), (i / width).as_i16());
+                    let rect = Rect { x, y, width: size, height: size };
+                    let damaged = (0..width * width)
+                        .map(|j| rect.contains((j % width).as_i16(), (j / width).as_i16()))
+                        .collect::<Vec<_>>();
+                    let flipped = colors
+                        .iter()
+                        .zip(&damaged)
+                        .map(|(color, damaged)| if *damaged { !*color } else { *color })
+                        .collect::<Vec<_>>();
+                    let decodes = decode_matrix(&flipped, width)
+                        .is_ok_and(|decoded| decoded.data == data.as_bytes());
+                    let report = budget.assess(&damaged);
+                    assert_eq!(report, budget.assess_rect(rect));
+                    assert_eq!(report.is_decodable(), decodes, "{data} {rect:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_heat_map() {
+        let budget = EcBudget::new(Version::Normal(2), EcLevel::M).unwrap();
+        let width = budget.width();
+        let correctable = budget.correctable_errors() as f64;
+        for size in [1, 3, 4] {
+            let heat = budget.heat_map(size);
+            assert_eq!(heat.len(), (width * width).as_usize());
+            for (i, value) in heat.iter().enumerate() {
+                let (x, y) = (i.as_i16() % width, i.as_i16() / width);
+                let report = budget.assess_rect(Rect {
+                    x: x - (size - 1) / 2,
+                    y: y - (size - 1) / 2,
+                    width: size,
+                    height: size,
+                });
+                if report.damaged_finder_modules > 0 || !report.format_info_readable {
+                    assert_eq!(*value, f64::INFINITY);
+                } else {
+                    let worst = report.damaged_codewords.iter().copied().max().unwrap();
+                    assert_eq!(*value, worst as f64 / correctable);
+                }
+            }
+        }
+    }
+}
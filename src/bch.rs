@@ -0,0 +1,263 @@
+//! The BCH codes protecting the format and version information.
+//!
+//! The format information is a (15, 5) BCH code with generator 0x537, XORed
+//! with 0x5412 so it is never all light. The version information is an
+//! (18, 6) BCH code with generator 0x1f25. Both codes have a minimum distance
+//! of at least 7, so up to 3 wrong bits can always be corrected.
+//!
+//! Words read from a symbol are decoded by picking the nearest valid word by
+//! Hamming distance, and the distance is reported as a measure of confidence.
+
+use crate::canvas::{
+    MaskPattern, ALL_PATTERNS_QR, FORMAT_INFO_COORDS_QR_MAIN, FORMAT_INFO_COORDS_QR_SIDE,
+    VERSION_INFO_COORDS_BL, VERSION_INFO_COORDS_TR,
+};
+use crate::cast::As;
+use crate::types::{Color, EcLevel, QrError, QrResult, Version};
+
+/// The generator polynomial of the format information code.
+const FORMAT_GENERATOR: u32 = 0x537;
+
+/// The mask XORed with the format information.
+const FORMAT_MASK: u32 = 0x5412;
+
+/// The generator polynomial of the version information code.
+const VERSION_GENERATOR: u32 = 0x1f25;
+
+/// The largest distance at which a decoded word is guaranteed to be the one
+/// which was written.
+pub const MAX_CORRECTABLE_DISTANCE: u32 = 3;
+
+/// The content of the format information.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatInfo {
+    pub ec_level: EcLevel,
+    pub mask_pattern: MaskPattern,
+}
+
+/// A decoded word together with the number of bits which differ from the
+/// nearest valid word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BchMatch<T> {
+    pub value: T,
+    pub distance: u32,
+}
+
+impl<T> BchMatch<T> {
+    /// Whether the distance is small enough for the value to be trusted.
+    pub const fn is_reliable(&self) -> bool {
+        self.distance <= MAX_CORRECTABLE_DISTANCE
+    }
+}
+
+/// Returns the remainder of `data << (degree of generator)` divided by the
+/// generator, i.e. the check bits of the systematic code.
+fn bch_remainder(data: u32, generator: u32) -> u32 {
+    let degree = 31 - generator.leading_zeros();
+    let mut rem = data << degree;
+    while rem >> degree != 0 {
+        let shift = (31 - rem.leading_zeros()) - degree;
+        rem ^= generator << shift;
+    }
+    rem
+}
+
+/// Encodes the 15-bit format information word, including the mask.
+pub fn encode_format_info(info: FormatInfo) -> u16 {
+    let data = (((info.ec_level as u32) ^ 1) << 3) | info.mask_pattern as u32;
+    let word = (data << 10 | bch_remainder(data, FORMAT_GENERATOR)) ^ FORMAT_MASK;
+    word.as_u16()
+}
+
+/// Encodes the 18-bit version information word. Only versions 7 to 40 carry
+/// version information.
+pub fn encode_version_info(version: Version) -> QrResult<u32> {
+    match version {
+        Version::Normal(v @ 7..=40) => {
+            let data = v.as_u32();
+            Ok(data << 12 | bch_remainder(data, VERSION_GENERATOR))
+        }
+        Version::Normal(_) => Err(QrError::InvalidVersion),
+    }
+}
+
+/// Returns the candidate whose word is nearest to `word`.
+fn nearest<T, I: Iterator<Item = (T, u32)>>(word: u32, candidates: I) -> BchMatch<T> {
+    candidates
+        .map(|(value, candidate)| BchMatch {
+            value,
+            distance: (word ^ candidate).count_ones(),
+        })
+        .min_by_key(|m| m.distance)
+        .expect("non-empty candidates")
+}
+
+/// Decodes a possibly damaged format information word.
+pub fn decode_format_info(word: u16) -> BchMatch<FormatInfo> {
+    let candidates = [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H]
+        .into_iter()
+        .flat_map(|ec_level| {
+            ALL_PATTERNS_QR.iter().map(move |mask_pattern| FormatInfo {
+                ec_level,
+                mask_pattern: *mask_pattern,
+            })
+        })
+        .map(|info| (info, u32::from(encode_format_info(info))));
+    nearest(u32::from(word), candidates)
+}
+
+/// Decodes a possibly damaged version information word.
+pub fn decode_version_info(word: u32) -> BchMatch<Version> {
+    let candidates = (7..=40).map(|v| {
+        let version = Version::Normal(v);
+        (version, encode_version_info(version).expect("valid version"))
+    });
+    nearest(word, candidates)
+}
+
+/// Reads the modules at `coords` of a `width × width` matrix as a number,
+/// most significant bit first. Negative coordinates wrap around.
+fn read_number(colors: &[Color], width: usize, coords: &[(i16, i16)]) -> u32 {
+    let width = width.as_i16();
+    coords.iter().fold(0, |acc, &(x, y)| {
+        let x = if x < 0 { x + width } else { x };
+        let y = if y < 0 { y + width } else { y };
+        acc << 1 | u32::from(colors[(y * width + x).as_usize()] == Color::Dark)
+    })
+}
+
+/// Decodes both copies of the format information of a matrix: the one around
+/// the top-left finder pattern, then the one split between the other two.
+pub fn read_format_info_copies(colors: &[Color], width: usize) -> [BchMatch<FormatInfo>; 2] {
+    [&FORMAT_INFO_COORDS_QR_MAIN, &FORMAT_INFO_COORDS_QR_SIDE]
+        .map(|coords| decode_format_info(read_number(colors, width, coords).as_u16()))
+}
+
+/// Returns the most reliable copy of the format information of a matrix.
+pub fn read_format_info(colors: &[Color], width: usize) -> QrResult<BchMatch<FormatInfo>> {
+    let [main, side] = read_format_info_copies(colors, width);
+    let best = if side.distance < main.distance { side } else { main };
+    if best.is_reliable() {
+        Ok(best)
+    } else {
+        Err(QrError::InvalidFormatInfo)
+    }
+}
+
+/// Decodes both copies of the version information of a matrix: the one at the
+/// bottom left, then the one at the top right.
+pub fn read_version_info_copies(colors: &[Color], width: usize) -> [BchMatch<Version>; 2] {
+    [&VERSION_INFO_COORDS_BL, &VERSION_INFO_COORDS_TR]
+        .map(|coords| decode_version_info(read_number(colors, width, coords)))
+}
+
+/// Returns the most reliable copy of the version information of a matrix.
+/// Symbols narrower than version 7 have none, which is reported as
+/// `QrError::InvalidVersion`.
+pub fn read_version_info(colors: &[Color], width: usize) -> QrResult<BchMatch<Version>> {
+    if width < Version::Normal(7).width().as_usize() {
+        return Err(QrError::InvalidVersion);
+    }
+    let [bl, tr] = read_version_info_copies(colors, width);
+    let best = if tr.distance < bl.distance { tr } else { bl };
+    if best.is_reliable() {
+        Ok(best)
+    } else {
+        Err(QrError::InvalidVersion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::{FORMAT_INFOS_QR, VERSION_INFOS};
+
+    /// Every word which differs from `word` in at most 3 of its low `bits`
+    /// bits, with the number of differing bits.
+    fn damaged(word: u32, bits: u32) -> Vec<(u32, u32)> {
+        let mut words = vec![(word, 0)];
+        for a in 0..bits {
+            words.push((word ^ 1 << a, 1));
+            for b in a + 1..bits {
+                words.push((word ^ 1 << a ^ 1 << b, 2));
+                for c in b + 1..bits {
+                    words.push((word ^ 1 << a ^ 1 << b ^ 1 << c, 3));
+                }
+            }
+        }
+        words
+    }
+
+    fn all_format_infos() -> impl Iterator<Item = FormatInfo> {
+        [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H]
+            .into_iter()
+            .flat_map(|ec_level| {
+                ALL_PATTERNS_QR.iter().map(move |mask_pattern| FormatInfo {
+                    ec_level,
+                    mask_pattern: *mask_pattern,
+                })
+            })
+    }
+
+    #[test]
+    fn test_encode_format_info() {
+        for info in all_format_infos() {
+            let index = ((info.ec_level as usize) ^ 1) << 3 | info.mask_pattern as usize;
+            assert_eq!(encode_format_info(info), FORMAT_INFOS_QR[index], "{info:?}");
+        }
+    }
+
+    #[test]
+    fn test_encode_version_info() {
+        for v in 7..=40 {
+            let expected = VERSION_INFOS[(v - 7).as_usize()];
+            assert_eq!(encode_version_info(Version::Normal(v)), Ok(expected), "version {v}");
+        }
+        for v in 1..7 {
+            assert_eq!(encode_version_info(Version::Normal(v)), Err(QrError::InvalidVersion));
+        }
+    }
+
+    #[test]
+    fn test_decode_format_info() {
+        for info in all_format_infos() {
+            for (word, flipped) in damaged(u32::from(encode_format_info(info)), 15) {
+                let expected = BchMatch {
+                    value: info,
+                    distance: flipped,
+                };
+                assert_eq!(decode_format_info(word.as_u16()), expected, "{word:015b}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_version_info() {
+        for v in 7..=40 {
+            let version = Version::Normal(v);
+            for (word, flipped) in damaged(encode_version_info(version).unwrap(), 18) {
+                let expected = BchMatch {
+                    value: version,
+                    distance: flipped,
+                };
+                assert_eq!(decode_version_info(word), expected, "{word:018b}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_damaged_format_info() {
+        let code = crate::qr_code::QrCode::with_error_correction_level(b"bch", EcLevel::Q).unwrap();
+        let (mut colors, width) = (code.to_colors(), code.width());
+        let expected = read_format_info(&colors, width).unwrap().value;
+        assert_eq!(expected.ec_level, EcLevel::Q);
+        for (x, y) in &FORMAT_INFO_COORDS_QR_MAIN[..4] {
+            let i = (y * width.as_i16() + x).as_usize();
+            colors[i] = !colors[i];
+        }
+        let [main, side] = read_format_info_copies(&colors, width);
+        assert_eq!(main.distance, 4);
+        assert_eq!(side.value, expected);
+        assert_eq!(read_format_info(&colors, width).unwrap().value, expected);
+    }
+}
@@ -1,4 +1,9 @@
 use std::cmp::min;
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+use serde_json::json;
+
 use crate::cast::{As, Truncate};
 use crate::optimize::{total_encoded_len, Optimizer, Parser, Segment};
 use crate::types::{EcLevel, Mode, QrError, QrResult, Version};
@@ -65,6 +70,23 @@ fn alphanumeric_digit(character: u8) -> u16 {
     }
 }
 
+/// Formats a run of input bytes for an annotation, escaping anything which
+/// is not printable ASCII.
+fn quote(bytes: &[u8]) -> String {
+    let text = bytes.iter().map(|b| b.escape_ascii().to_string()).collect::<String>();
+    format!("'{text}'")
+}
+
+/// The 4-bit mode indicator of a data segment.
+const fn mode_indicator(mode: Mode) -> u16 {
+    match mode {
+        Mode::Numeric => 0b0001,
+        Mode::Alphanumeric => 0b0010,
+        Mode::Byte => 0b0100,
+        Mode::Kanji => 0b1000,
+    }
+}
+
 fn find_min_version(length: usize, ec_level: EcLevel) -> Version {
     let mut base: usize = 0_usize;
     let mut size = 39;
@@ -104,22 +126,27 @@ fn find_min_version(length: usize, ec_level: EcLevel) -> Version {
  * 如果数据过长无法编码，则返回错误
  */
 pub fn encode_auto(data: &[u8], ec_level: EcLevel) -> QrResult<Bits> {
+    let (min_version, opt_segments) = optimal_segments(data, ec_level)?;
+    let mut bits = Bits::new(min_version);
+    bits.reserve(total_encoded_len(&opt_segments, min_version));
+    bits.push_segments(data, opt_segments.into_iter())?;
+    bits.push_terminator(ec_level)?;
+    Ok(bits)
+}
+
+/// Returns the segments `encode_auto` would encode the data as, together with
+/// the smallest version which can hold them.
+pub fn optimal_segments(data: &[u8], ec_level: EcLevel) -> QrResult<(Version, Vec<Segment>)> {
     let segments = Parser::new(data).collect::<Vec<Segment>>();
     for version in &[Version::Normal(9), Version::Normal(26), Version::Normal(40)] {
         let opt_segments = Optimizer::new(segments.iter().copied(), *version).collect::<Vec<_>>();
-        println!("opt_segments: {:?}", opt_segments);
         let total_len = total_encoded_len(&opt_segments, *version);
         let data_capacity = version
             .fetch(ec_level, &DATA_LENGTHS)
             .expect("invalid DATA_LENGTHS");
         if total_len <= data_capacity {
             let min_version = find_min_version(total_len, ec_level);
-            println!("min_version: {:?}", min_version);
-            let mut bits = Bits::new(min_version);
-            bits.reserve(total_len);
-            bits.push_segments(data, opt_segments.into_iter())?;
-            bits.push_terminator(ec_level)?;
-            return Ok(bits);
+            return Ok((min_version, opt_segments));
         }
     }
     Err(QrError::DataTooLong)
@@ -131,11 +158,61 @@ pub enum ExtendedMode {
     Data(Mode),
 }
 
+/// Describes what a range of bits in a `Bits` stream means.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnotationKind {
+    /// The 4-bit mode indicator.
+    ModeIndicator(Mode),
+
+    /// The character count indicator following the mode indicator.
+    CharacterCount(usize),
+
+    /// A group of characters packed into a single number, e.g. up to 3 digits
+    /// in Numeric mode or 2 characters in Alphanumeric mode.
+    Data { text: String, value: u16 },
+
+    /// The terminator, i.e. up to 4 zero bits.
+    Terminator,
+
+    /// Zero bits filling up the last byte before the pad codewords.
+    BitPadding,
+
+    /// A pad codeword (`0xEC` or `0x11`).
+    PadByte(u8),
+}
+
+impl fmt::Display for AnnotationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ModeIndicator(mode) => {
+                let name = format!("{mode:?}").to_lowercase();
+                write!(f, "mode={:04b} {name}", mode_indicator(*mode))
+            }
+            Self::CharacterCount(count) => write!(f, "count={count}"),
+            Self::Data { text, value } => write!(f, "{text} → {value}"),
+            Self::Terminator => f.write_str("terminator"),
+            Self::BitPadding => f.write_str("pad to byte boundary"),
+            Self::PadByte(b) => write!(f, "pad 0x{b:02X}"),
+        }
+    }
+}
+
+/// A range of bits in a `Bits` stream together with its meaning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    /// The bit positions covered, counting from the start of the stream.
+    pub range: Range<usize>,
+
+    /// What these bits mean.
+    pub kind: AnnotationKind,
+}
+
 #[derive(Debug)]
 pub struct Bits {
     data: Vec<u8>,
     bit_offset: usize,
     version: Version,
+    annotations: Vec<Annotation>,
 }
 
 impl Bits {
@@ -148,6 +225,7 @@ impl Bits {
             data: Vec::new(),
             bit_offset: 0,
             version,
+            annotations: Vec::new(),
         }
     }
     pub fn push_segments<I>(&mut self, data: &[u8], segments_iter: I) -> QrResult<()>
@@ -178,12 +256,17 @@ impl Bits {
         let terminator_size = min(terminator_size, data_length - cur_length);
         if terminator_size > 0 {
             self.push_number(terminator_size, 0);
+            self.annotate(cur_length, AnnotationKind::Terminator);
         }
 
         if self.len() < data_length {
             const PADDING_BYTES: &[u8] = &[0b1110_1100, 0b0001_0001];
 
-            self.bit_offset = 0;
+            if self.bit_offset != 0 {
+                let start = self.len();
+                self.bit_offset = 0;
+                self.annotate(start, AnnotationKind::BitPadding);
+            }
             let data_bytes_length = data_length / 8;
             let padding_bytes_count = data_bytes_length - self.data.len();
             let padding = PADDING_BYTES
@@ -191,11 +274,17 @@ impl Bits {
                 .copied()
                 .cycle()
                 .take(padding_bytes_count);
-            self.data.extend(padding);
+            for b in padding {
+                let start = self.len();
+                self.data.push(b);
+                self.annotate(start, AnnotationKind::PadByte(b));
+            }
         }
 
         if self.len() < data_length {
+            let start = self.len();
             self.data.push(0);
+            self.annotate(start, AnnotationKind::BitPadding);
         }
 
         Ok(())
@@ -208,7 +297,7 @@ impl Bits {
                 .map(|b| u16::from(*b - b'0'))
                 .fold(0, |a, b| a * 10 + b);
             let length = chunk.len() * 3 + 1;
-            self.push_number(length, number);
+            self.push_annotated_number(length, number, quote(chunk));
         }
         Ok(())
     }
@@ -220,7 +309,7 @@ impl Bits {
                 .map(|b| alphanumeric_digit(*b))
                 .fold(0, |a, b| a * 45 + b);
             let length = chunk.len() * 5 + 1;
-            self.push_number(length, number);
+            self.push_annotated_number(length, number, quote(chunk));
         }
         Ok(())
     }
@@ -237,36 +326,45 @@ impl Bits {
                 cp - 0xc140
             };
             let number = (bytes >> 8) * 0xc0 + (bytes & 0xff);
-            self.push_number(13, number);
+            self.push_annotated_number(13, number, format!("0x{cp:04X}"));
         }
         Ok(())
     }
     pub fn push_byte_data(&mut self, data: &[u8]) -> QrResult<()> {
         self.push_header(Mode::Byte, data.len())?;
         for b in data {
-            self.push_number(8, u16::from(*b));
+            self.push_annotated_number(8, u16::from(*b), quote(&[*b]));
         }
         Ok(())
     }
     pub fn push_mode_indicator(&mut self, mode: ExtendedMode) -> QrResult<()> {
-        #[allow(clippy::match_same_arms)]
-        let number = match (self.version, mode) {
-            (_, ExtendedMode::Data(Mode::Numeric)) => 0b0001,
-            (_, ExtendedMode::Data(Mode::Alphanumeric)) => 0b0010,
-            (_, ExtendedMode::Data(Mode::Byte)) => 0b0100,
-            (_, ExtendedMode::Data(Mode::Kanji)) => 0b1000,
-        };
+        let ExtendedMode::Data(mode) = mode;
+        let number = mode_indicator(mode);
         let bits = self.version.mode_bits_count();
-        self.push_number_checked(bits, number)
-            .or(Err(QrError::UnsupportedCharacterSet))
+        let start = self.len();
+        self.push_number_checked(bits, usize::from(number))
+            .or(Err(QrError::UnsupportedCharacterSet))?;
+        self.annotate(start, AnnotationKind::ModeIndicator(mode));
+        Ok(())
     }
     fn push_header(&mut self, mode: Mode, raw_data_len: usize) -> QrResult<()> {
         let length_bits = mode.length_bits_count(self.version);
         self.reserve(length_bits + 4 + mode.data_bits_count(raw_data_len));
         self.push_mode_indicator(ExtendedMode::Data(mode))?;
+        let start = self.len();
         self.push_number_checked(length_bits, raw_data_len)?;
+        self.annotate(start, AnnotationKind::CharacterCount(raw_data_len));
         Ok(())
     }
+    fn push_annotated_number(&mut self, n: usize, number: u16, text: String) {
+        let start = self.len();
+        self.push_number(n, number);
+        self.annotate(start, AnnotationKind::Data { text, value: number });
+    }
+    fn annotate(&mut self, start: usize, kind: AnnotationKind) {
+        let range = start..self.len();
+        self.annotations.push(Annotation { range, kind });
+    }
     fn push_number(&mut self, n: usize, number: u16) {
         debug_assert!(
             n == 16 || n < 16 && number < (1 << n),
@@ -309,6 +407,72 @@ impl Bits {
     pub const fn version(&self) -> Version {
         self.version
     }
+    /// Returns the bit at the given position, counting from the most
+    /// significant bit of the first byte.
+    pub fn bit(&self, index: usize) -> bool {
+        self.data[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+    /// The annotations recorded so far, in stream order.
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+    /// Renders the annotations as a text table, one row per bit range, e.g.
+    ///
+    /// ```text
+    ///        0..4  0010              mode=0010 alphanumeric (4 bits)
+    ///       4..13  000001011         count=11 (9 bits)
+    ///      13..24  01100001011       'HE' → 779 (11 bits)
+    /// ```
+    pub fn annotation_table(&self) -> String {
+        let mut res = String::new();
+        for a in &self.annotations {
+            let range = format!("{}..{}", a.range.start, a.range.end);
+            let _ = writeln!(
+                res,
+                "{range:>11}  {:<16}  {} ({} bits)",
+                self.bit_string(&a.range),
+                a.kind,
+                a.range.len(),
+            );
+        }
+        res
+    }
+    /// Renders the annotations as a JSON array of
+    /// `{"start", "end", "bits", "kind", "meaning"}` objects.
+    pub fn annotation_json(&self) -> String {
+        self.annotation_json_value().to_string()
+    }
+    /// Same as `annotation_json`, but returns the unserialized value.
+    pub fn annotation_json_value(&self) -> serde_json::Value {
+        let rows = self
+            .annotations
+            .iter()
+            .map(|a| {
+                let kind = match a.kind {
+                    AnnotationKind::ModeIndicator(_) => "mode",
+                    AnnotationKind::CharacterCount(_) => "count",
+                    AnnotationKind::Data { .. } => "data",
+                    AnnotationKind::Terminator => "terminator",
+                    AnnotationKind::BitPadding => "bit_padding",
+                    AnnotationKind::PadByte(_) => "pad_byte",
+                };
+                json!({
+                    "start": a.range.start,
+                    "end": a.range.end,
+                    "bits": self.bit_string(&a.range),
+                    "kind": kind,
+                    "meaning": a.kind.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::Value::Array(rows)
+    }
+    fn bit_string(&self, range: &Range<usize>) -> String {
+        range
+            .clone()
+            .map(|i| if self.bit(i) { '1' } else { '0' })
+            .collect()
+    }
     pub fn into_bytes(self) -> Vec<u8> {
         self.data
     }
@@ -319,7 +483,79 @@ impl Bits {
             (self.data.len() - 1) * 8 + self.bit_offset
         }
     }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
     pub fn max_len(&self, ec_level: EcLevel) -> QrResult<usize> {
         self.version.fetch(ec_level, &DATA_LENGTHS)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "HELLO WORLD" at 1-Q, the example of ISO/IEC 18004 annex I.
+    fn hello_world() -> Bits {
+        let bits = encode_auto(b"HELLO WORLD", EcLevel::Q).unwrap();
+        assert_eq!(bits.version(), Version::Normal(1));
+        bits
+    }
+
+    #[test]
+    fn test_annotation_table() {
+        let table = hello_world().annotation_table();
+        let expected = [
+            "       0..4  0010              mode=0010 alphanumeric (4 bits)",
+            "      4..13  000001011         count=11 (9 bits)",
+            "     13..24  01100001011       'HE' → 779 (11 bits)",
+            "     24..35  01111000110       'LL' → 966 (11 bits)",
+            "     35..46  10001011100       'O ' → 1116 (11 bits)",
+            "     46..57  10110111000       'WO' → 1464 (11 bits)",
+            "     57..68  10011010100       'RL' → 1236 (11 bits)",
+            "     68..74  001101            'D' → 13 (6 bits)",
+            "     74..78  0000              terminator (4 bits)",
+            "     78..80  00                pad to byte boundary (2 bits)",
+            "     80..88  11101100          pad 0xEC (8 bits)",
+            "     88..96  00010001          pad 0x11 (8 bits)",
+            "    96..104  11101100          pad 0xEC (8 bits)",
+        ];
+        assert_eq!(table.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_annotation_json() {
+        let json = hello_world().annotation_json();
+        let rows = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        let rows = rows.as_array().unwrap();
+        assert_eq!(rows.len(), 13);
+        assert_eq!(
+            rows[0],
+            json!({
+                "start": 0,
+                "end": 4,
+                "bits": "0010",
+                "kind": "mode",
+                "meaning": "mode=0010 alphanumeric",
+            })
+        );
+        assert_eq!(
+            rows[2],
+            json!({
+                "start": 13,
+                "end": 24,
+                "bits": "01100001011",
+                "kind": "data",
+                "meaning": "'HE' → 779",
+            })
+        );
+        let kinds = rows.iter().map(|row| row["kind"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(kinds[1], "count");
+        assert_eq!(kinds[8..], ["terminator", "bit_padding", "pad_byte", "pad_byte", "pad_byte"]);
+        // The rows cover the stream without gaps.
+        for pair in rows.windows(2) {
+            assert_eq!(pair[0]["end"], pair[1]["start"]);
+        }
+        assert_eq!(rows[12]["end"], 104);
+    }
+}
@@ -2,9 +2,11 @@ use std::cmp::max;
 use std::iter;
 
 use crate::cast::As;
+use crate::mask::MaskStrategy;
 use crate::types::{Color, EcLevel, Version};
 
-static ALL_PATTERNS_QR: [MaskPattern; 8] = [
+/// All mask patterns of normal QR codes, in pattern number order.
+pub static ALL_PATTERNS_QR: [MaskPattern; 8] = [
     MaskPattern::Checkerboard,
     MaskPattern::HorizontalLines,
     MaskPattern::VerticalLines,
@@ -16,13 +18,13 @@ static ALL_PATTERNS_QR: [MaskPattern; 8] = [
 ];
 
 
-static FORMAT_INFOS_QR: [u16; 32] = [
+pub(crate) static FORMAT_INFOS_QR: [u16; 32] = [
     0x5412, 0x5125, 0x5e7c, 0x5b4b, 0x45f9, 0x40ce, 0x4f97, 0x4aa0, 0x77c4, 0x72f3, 0x7daa, 0x789d,
     0x662f, 0x6318, 0x6c41, 0x6976, 0x1689, 0x13be, 0x1ce7, 0x19d0, 0x0762, 0x0255, 0x0d0c, 0x083b,
     0x355f, 0x3068, 0x3f31, 0x3a06, 0x24b4, 0x2183, 0x2eda, 0x2bed,
 ];
 
-static FORMAT_INFO_COORDS_QR_MAIN: [(i16, i16); 15] = [
+pub(crate) static FORMAT_INFO_COORDS_QR_MAIN: [(i16, i16); 15] = [
     (0, 8),
     (1, 8),
     (2, 8),
@@ -40,7 +42,7 @@ static FORMAT_INFO_COORDS_QR_MAIN: [(i16, i16); 15] = [
     (8, 0),
 ];
 
-static FORMAT_INFO_COORDS_QR_SIDE: [(i16, i16); 15] = [
+pub(crate) static FORMAT_INFO_COORDS_QR_SIDE: [(i16, i16); 15] = [
     (8, -1),
     (8, -2),
     (8, -3),
@@ -58,7 +60,7 @@ static FORMAT_INFO_COORDS_QR_SIDE: [(i16, i16); 15] = [
     (-1, 8),
 ];
 
-static VERSION_INFO_COORDS_BL: [(i16, i16); 18] = [
+pub(crate) static VERSION_INFO_COORDS_BL: [(i16, i16); 18] = [
     (5, -9),
     (5, -10),
     (5, -11),
@@ -79,7 +81,7 @@ static VERSION_INFO_COORDS_BL: [(i16, i16); 18] = [
     (0, -11),
 ];
 
-static VERSION_INFO_COORDS_TR: [(i16, i16); 18] = [
+pub(crate) static VERSION_INFO_COORDS_TR: [(i16, i16); 18] = [
     (-9, 5),
     (-10, 5),
     (-11, 5),
@@ -100,7 +102,7 @@ static VERSION_INFO_COORDS_TR: [(i16, i16); 18] = [
     (-11, 0),
 ];
 
-static VERSION_INFOS: [u32; 34] = [
+pub(crate) static VERSION_INFOS: [u32; 34] = [
     0x07c94, 0x085bc, 0x09a99, 0x0a4d3, 0x0bbf6, 0x0c762, 0x0d847, 0x0e60d, 0x0f928, 0x10b78,
     0x1145d, 0x12a17, 0x13532, 0x149a6, 0x15683, 0x168c9, 0x177ec, 0x18ec4, 0x191e1, 0x1afab,
     0x1b08e, 0x1cc1a, 0x1d33f, 0x1ed75, 0x1f250, 0x209d5, 0x216f0, 0x228ba, 0x2379f, 0x24b0b,
@@ -172,7 +174,30 @@ mod mask_functions {
     }
 }
 
-fn get_mask_function(pattern: MaskPattern) -> fn(i16, i16) -> bool {
+/// Returns the centres of all alignment patterns of the version, skipping
+/// those which would overlap a finder pattern. Coordinates are non-negative.
+pub(crate) fn alignment_pattern_centers(version: Version) -> Vec<(i16, i16)> {
+    let width = version.width();
+    match version {
+        Version::Normal(1) => Vec::new(),
+        Version::Normal(2..=6) => vec![(width - 7, width - 7)],
+        Version::Normal(a) => {
+            let positions = ALIGNMENT_PATTERN_POSITIONS[(a - 7).as_usize()];
+            let last = width - 7;
+            let mut centers = Vec::with_capacity(positions.len() * positions.len());
+            for &y in positions {
+                for &x in positions {
+                    if (x, y) != (6, 6) && (x, y) != (6, last) && (x, y) != (last, 6) {
+                        centers.push((x, y));
+                    }
+                }
+            }
+            centers
+        }
+    }
+}
+
+pub(crate) fn get_mask_function(pattern: MaskPattern) -> fn(i16, i16) -> bool {
     match pattern {
         MaskPattern::Checkerboard => mask_functions::checkerboard,
         MaskPattern::HorizontalLines => mask_functions::horizontal_lines,
@@ -185,14 +210,14 @@ fn get_mask_function(pattern: MaskPattern) -> fn(i16, i16) -> bool {
     }
 }
 
-struct DataModuleIter {
+pub(crate) struct DataModuleIter {
     x: i16,
     y: i16,
     width: i16,
     timing_pattern_column: i16,
 }
 impl DataModuleIter{
-    const fn new(version: Version) -> Self {
+    pub(crate) const fn new(version: Version) -> Self {
         let width = version.width();
         Self {
             x: width - 1,
@@ -240,7 +265,7 @@ impl Iterator for DataModuleIter {
         Some(res)
     }
 }
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MaskPattern {
     /// QR code pattern 000: `(x + y) % 2 == 0`.
     Checkerboard = 0b000,
@@ -449,7 +474,9 @@ impl Canvas{
 
         total_score - 360
     }
-    fn compute_total_penalty_scores(&self) -> u16 {
+    /// Computes the ISO/IEC 18004 penalty score (rules N1 to N4) of the
+    /// canvas. This is only meaningful after a mask has been applied.
+    pub fn compute_total_penalty_scores(&self) -> u16 {
         match self.version {
             Version::Normal(_) => {
                 let s1_a = self.compute_adjacent_penalty_score(true);
@@ -493,6 +520,7 @@ impl Canvas{
         };
         self.draw_format_info_patterns_with_number(format_number);
     }
+    #[allow(dead_code)] // Only used by Micro QR codes.
     fn compute_light_side_penalty_score(&self) -> u16 {
         let h = (1..self.width)
             .filter(|j| !self.get(*j, -1).is_dark())
@@ -520,10 +548,10 @@ impl Canvas{
             }
         }
     }
-    fn draw_reserved_format_info_patterns(&mut self) {
+    pub fn draw_reserved_format_info_patterns(&mut self) {
         self.draw_format_info_patterns_with_number(0);
     }
-    fn draw_timing_patterns(&mut self) {
+    pub fn draw_timing_patterns(&mut self) {
         let width = self.width;
         let (y, x1, x2) = match self.version {
             Version::Normal(_) => (6, 8, width - 9),
@@ -531,7 +559,7 @@ impl Canvas{
         self.draw_line(x1, y, x2, y, Color::Dark, Color::Light);
         self.draw_line(y, x1, y, x2, Color::Dark, Color::Light);
     }
-    fn draw_alignment_patterns(&mut self) {
+    pub fn draw_alignment_patterns(&mut self) {
         match self.version {
             Version::Normal(1) => {}
             Version::Normal(2..=6) => self.draw_alignment_pattern_at(-7, -7),
@@ -605,7 +633,7 @@ impl Canvas{
      * - 确定QR码的方向
      * - 计算QR码的大小和版本
      */
-    fn draw_finder_patterns(&mut self) {
+    pub fn draw_finder_patterns(&mut self) {
         self.draw_finder_pattern_at(3, 3);
 
         match self.version {
@@ -619,14 +647,9 @@ impl Canvas{
         let dark_modules = self.modules.iter().filter(|m| m.is_dark()).count();
         let total_modules = self.modules.len();
         let ratio = dark_modules * 200 / total_modules;
-        if ratio >= 100 {
-            ratio - 100
-        } else {
-            100 - ratio
-        }
-        .as_u16()
+        ratio.abs_diff(100).as_u16()
     }
-    fn draw_version_info_patterns(&mut self) {
+    pub fn draw_version_info_patterns(&mut self) {
         match self.version {
             Version::Normal(1..=6) => {}
             Version::Normal(a) => {
@@ -726,18 +749,35 @@ impl Canvas{
         self.draw_codewords(data, false, &mut coords);
         self.draw_codewords(ec, false, &mut coords);
     }
-    pub fn apply_best_mask(&self) -> Self {
+    /// Tries every mask pattern and keeps the candidate with the lowest score
+    /// according to `strategy`. Ties are resolved in favour of the lower
+    /// pattern number.
+    pub fn apply_best_mask<S: MaskStrategy + ?Sized>(&self, strategy: &S) -> Self {
+        self.select_mask(strategy).1
+    }
+    /// Like `apply_best_mask`, but also returns the selected pattern.
+    pub fn select_mask<S: MaskStrategy + ?Sized>(&self, strategy: &S) -> (MaskPattern, Self) {
         match self.version {
             Version::Normal(_) => ALL_PATTERNS_QR.iter(),
         }
             .map(|ptn| {
                 let mut c = self.clone();
                 c.apply_mask(*ptn);
-                c
+                (strategy.score(*ptn, &c), *ptn, c)
             })
-            .min_by_key(Self::compute_total_penalty_scores)
+            .min_by_key(|(score, _, _)| *score)
+            .map(|(_, ptn, c)| (ptn, c))
             .expect("at least one pattern")
     }
+    pub const fn width(&self) -> i16 {
+        self.width
+    }
+    pub const fn version(&self) -> Version {
+        self.version
+    }
+    pub const fn error_correction_level(&self) -> EcLevel {
+        self.ec_level
+    }
     pub fn into_colors(self) -> Vec<Color> {
         self.modules.into_iter().map(Color::from).collect()
     }
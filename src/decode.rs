@@ -0,0 +1,438 @@
+//! Reading a module matrix back into the data it encodes.
+//!
+//! This reverses the encoding pipeline: the format information is read and
+//! corrected, the mask is removed, the codewords are collected along the
+//! zigzag path of `DataModuleIter`, de-interleaved and corrected block by
+//! block, and finally the bit stream is parsed by `crate::parse`.
+
+use crate::bch;
+use crate::canvas::{get_mask_function, Canvas, DataModuleIter, MaskPattern, Module};
+use crate::cast::As;
+use crate::ec;
+use crate::parse::{self, ParsedSegment};
+use crate::types::{Color, EcLevel, QrError, QrResult, Version};
+
+/// The result of decoding a symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    /// The decoded data. Kanji characters are returned as Shift JIS bytes.
+    pub data: Vec<u8>,
+
+    /// The segments the data was encoded as, including any ECI, FNC1 and
+    /// Structured Append headers and the terminator.
+    pub segments: Vec<ParsedSegment>,
+
+    pub version: Version,
+
+    pub ec_level: EcLevel,
+
+    pub mask_pattern: MaskPattern,
+
+    /// The number of errors corrected in each block, in the order defined by
+    /// `DATA_BYTES_PER_BLOCK`: codewords which were read but wrong. Erasures
+    /// are not included, so a block used `2 × corrections + erasures` of its
+    /// error correction capacity.
+    pub corrections: Vec<usize>,
+
+    /// The number of codewords containing unknown modules in each block,
+    /// which were recovered as erasures, whether or not their guessed value
+    /// turned out to be right. All zero for `decode_matrix`.
+    pub erasures: Vec<usize>,
+
+    /// Whether the symbol is mirror-imaged, i.e. had to be transposed before
+    /// it could be decoded.
+    pub mirrored: bool,
+
+    /// The corrected data codewords, block after block: the bit stream which
+    /// `segments` were parsed from.
+    pub data_codewords: Vec<u8>,
+}
+
+impl Decoded {
+    /// The matrix as it was meant to be, with all corrections applied, in
+    /// the same form as `decode_matrix` takes. Mirrored symbols are returned
+    /// the right way round, i.e. transposed relative to their input.
+    pub fn ideal_matrix(&self) -> Vec<Color> {
+        let (data, ec) = ec::construct_codewords(&self.data_codewords, self.version, self.ec_level)
+            .expect("decoded version and EC level");
+        let mut canvas = Canvas::new(self.version, self.ec_level);
+        canvas.draw_all_functional_patterns();
+        canvas.draw_data(&data, &ec);
+        canvas.apply_mask(self.mask_pattern);
+        canvas.into_colors()
+    }
+}
+
+/// Decodes a symbol given as `width × width` colors in left-to-right, then
+/// top-to-bottom order, without quiet zone.
+///
+/// Mirror-imaged symbols are accepted as well: if the matrix cannot be
+/// decoded as it is, it is transposed and decoded again. A mirrored format
+/// information word often decodes to a wrong but seemingly reliable value, so
+/// this is done after any error, not only after `QrError::InvalidFormatInfo`.
+/// The error of the first attempt is returned if both fail.
+pub fn decode_matrix(colors: &[Color], width: usize) -> QrResult<Decoded> {
+    let modules = colors.iter().copied().map(Some).collect::<Vec<_>>();
+    decode_partial_matrix(&modules, width)
+}
+
+/// Like `decode_matrix`, but modules may be unknown (`None`), e.g. where the
+/// symbol is torn or covered.
+///
+/// Every codeword containing an unknown module is treated as an erasure.
+/// Reed-Solomon decoding can recover twice as many erasures as errors, so
+/// marking damaged modules as unknown rather than guessing their color
+/// recovers more damage. Unknown modules of the format and version
+/// information are read as light; their BCH codes and the second copies
+/// usually make up for it.
+pub fn decode_partial_matrix(modules: &[Option<Color>], width: usize) -> QrResult<Decoded> {
+    if !(21..=177).contains(&width)
+        || !(width - 17).is_multiple_of(4)
+        || modules.len() != width * width
+    {
+        return Err(QrError::InvalidVersion);
+    }
+    decode_oriented(modules, width, false).or_else(|error| {
+        let transposed = (0..width * width)
+            .map(|i| modules[i % width * width + i / width])
+            .collect::<Vec<_>>();
+        decode_oriented(&transposed, width, true).map_err(|_| error)
+    })
+}
+
+/// Decodes a matrix whose width has been validated, in the given orientation.
+fn decode_oriented(modules: &[Option<Color>], width: usize, mirrored: bool) -> QrResult<Decoded> {
+    let version = Version::Normal(((width - 17) / 4).as_i16());
+    let colors = modules.iter().map(|m| m.unwrap_or(Color::Light)).collect::<Vec<_>>();
+
+    // The width is authoritative; the version information only has to agree
+    // with it when it can be read at all.
+    if let Ok(info) = bch::read_version_info(&colors, width)
+        && info.value != version
+    {
+        return Err(QrError::InvalidVersion);
+    }
+    let bch::FormatInfo {
+        ec_level,
+        mask_pattern,
+    } = bch::read_format_info(&colors, width)?.value;
+    let (codewords, unknown) = read_codewords(modules, version, ec_level, mask_pattern)?;
+    let (data, corrections, erasures) = correct_codewords(&codewords, &unknown, version, ec_level)?;
+    let stream = parse::parse(&data, version)?;
+    Ok(Decoded {
+        data: stream.data(),
+        segments: stream.segments,
+        version,
+        ec_level,
+        mask_pattern,
+        corrections,
+        erasures,
+        mirrored,
+        data_codewords: data,
+    })
+}
+
+/// Removes the mask and reads the codewords along the zigzag path, together
+/// with whether each of them contains unknown modules. Unknown modules are
+/// read as zero bits.
+fn read_codewords(
+    modules: &[Option<Color>],
+    version: Version,
+    ec_level: EcLevel,
+    pattern: MaskPattern,
+) -> QrResult<(Vec<u8>, Vec<bool>)> {
+    let (lengths, ec_len) = ec::block_layout(version, ec_level)?;
+    let total = lengths.iter().sum::<usize>() + lengths.len() * ec_len;
+
+    let mut functional = Canvas::new(version, ec_level);
+    functional.draw_all_functional_patterns();
+    let mask_fn = get_mask_function(pattern);
+    let width = version.width();
+    let bits = DataModuleIter::new(version)
+        .filter(|(x, y)| functional.get(*x, *y) == Module::Empty)
+        .map(|(x, y)| {
+            modules[(y * width + x).as_usize()].map(|color| (color == Color::Dark) != mask_fn(x, y))
+        })
+        .collect::<Vec<_>>();
+    let bytes = bits.chunks_exact(8).take(total);
+    Ok((
+        bytes
+            .clone()
+            .map(|byte| byte.iter().fold(0, |acc, bit| acc << 1 | u8::from(bit.unwrap_or(false))))
+            .collect(),
+        bytes.map(|byte| byte.contains(&None)).collect(),
+    ))
+}
+
+/// De-interleaves the codewords and corrects every block, returning the data
+/// codewords, the number of errors per block and the number of erasures per
+/// block.
+///
+/// The error correction codewords which the smallest symbols set aside for
+/// misdecode protection (see `ec::misdecode_protection`) do not count towards
+/// the capacity: a block which needed more than the rest is rejected, as its
+/// correction may well be a misdecode.
+fn correct_codewords(
+    codewords: &[u8],
+    unknown: &[bool],
+    version: Version,
+    ec_level: EcLevel,
+) -> QrResult<(Vec<u8>, Vec<usize>, Vec<usize>)> {
+    let (lengths, ec_len) = ec::block_layout(version, ec_level)?;
+    let capacity = ec_len - ec::misdecode_protection(version, ec_level);
+    let data_len = lengths.iter().sum::<usize>();
+    let ec_lengths = vec![ec_len; lengths.len()];
+    let data_blocks = ec::deinterleave(&codewords[..data_len], &lengths);
+    let ec_blocks = ec::deinterleave(&codewords[data_len..], &ec_lengths);
+    let unknown_data = ec::deinterleave(&unknown[..data_len], &lengths);
+    let unknown_ec = ec::deinterleave(&unknown[data_len..], &ec_lengths);
+
+    let mut data = Vec::with_capacity(data_len);
+    let mut corrections = Vec::with_capacity(lengths.len());
+    let mut erasures = Vec::with_capacity(lengths.len());
+    for (i, (data_block, ec_block)) in data_blocks.iter().zip(&ec_blocks).enumerate() {
+        let mut block = [data_block.as_slice(), ec_block].concat();
+        let erased = unknown_data[i]
+            .iter()
+            .chain(&unknown_ec[i])
+            .enumerate()
+            .filter_map(|(j, unknown)| unknown.then_some(j))
+            .collect::<Vec<_>>();
+        let read = block.clone();
+        ec::correct_errors(&mut block, ec_len, &erased)?;
+        let errors = (0..block.len())
+            .filter(|j| block[*j] != read[*j] && erased.binary_search(j).is_err())
+            .count();
+        if 2 * errors + erased.len() > capacity {
+            return Err(QrError::TooManyErrors);
+        }
+        corrections.push(errors);
+        erasures.push(erased.len());
+        data.extend_from_slice(&block[..data_block.len()]);
+    }
+    Ok((data, corrections, erasures))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mask::IsoPenalty;
+    use crate::parse::SegmentKind;
+    use crate::pipeline::EncodedBits;
+    use crate::qr_code::QrCode;
+    use crate::types::Mode;
+
+    const EC_LEVELS: [EcLevel; 4] = [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H];
+
+    /// "点茗" in Shift JIS, the Kanji example of ISO/IEC 18004.
+    const KANJI: [u8; 4] = [0x93, 0x5f, 0xe4, 0xaa];
+
+    /// Encodes `data`, decodes the symbol and checks that nothing changed on
+    /// the way.
+    fn round_trip(data: &[u8], ec_level: EcLevel) -> Decoded {
+        let code = QrCode::with_error_correction_level(data, ec_level).unwrap();
+        let decoded = decode_matrix(&code.to_colors(), code.width()).unwrap();
+        assert_eq!(decoded.data, data);
+        assert_eq!(decoded.version, code.version());
+        assert_eq!(decoded.ec_level, ec_level);
+        assert!(decoded.corrections.iter().all(|c| *c == 0));
+        assert!(!decoded.mirrored);
+        assert_eq!(decoded.ideal_matrix(), code.to_colors());
+        decoded
+    }
+
+    fn data_modes(decoded: &Decoded) -> Vec<Mode> {
+        decoded
+            .segments
+            .iter()
+            .filter_map(|s| match s.kind {
+                SegmentKind::Data { mode, .. } => Some(mode),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Round-trips a short and a long payload which are both encoded in
+    /// `mode` only, at every EC level.
+    fn check_mode(short: &[u8], long: &[u8], mode: Mode) {
+        for ec_level in EC_LEVELS {
+            let decoded = round_trip(short, ec_level);
+            assert_eq!(data_modes(&decoded), [mode]);
+            assert_eq!(decoded.version, Version::Normal(1));
+
+            let decoded = round_trip(long, ec_level);
+            assert_eq!(data_modes(&decoded), [mode]);
+            assert!(matches!(decoded.version, Version::Normal(20..)), "{:?}", decoded.version);
+        }
+    }
+
+    #[test]
+    fn test_numeric() {
+        check_mode(b"01234567", &b"3141592653".repeat(300), Mode::Numeric);
+    }
+
+    #[test]
+    fn test_alphanumeric() {
+        check_mode(b"HELLO", &b"AC-42 $%*+./:".repeat(140), Mode::Alphanumeric);
+    }
+
+    #[test]
+    fn test_byte() {
+        check_mode(b"hello", &b"the quick brown fox ".repeat(60), Mode::Byte);
+    }
+
+    #[test]
+    fn test_kanji() {
+        check_mode(&KANJI, &KANJI.repeat(250), Mode::Kanji);
+    }
+
+    #[test]
+    fn test_empty() {
+        for ec_level in EC_LEVELS {
+            let decoded = round_trip(b"", ec_level);
+            assert_eq!(decoded.version, Version::Normal(1));
+            assert!(data_modes(&decoded).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_mixed_modes() {
+        for ec_level in EC_LEVELS {
+            round_trip(b"0123456789012345HELLO WORLD hello \x93\x5f\xe4\xaa", ec_level);
+        }
+    }
+
+    #[test]
+    fn test_every_version() {
+        for n in (1..2900).step_by(143) {
+            round_trip(&b"x".repeat(n), EcLevel::L);
+        }
+    }
+
+    /// A version 11-H symbol and a predicate for the `size × size` square in
+    /// its middle.
+    fn covered_symbol(size: usize) -> (Vec<u8>, QrCode, impl Fn(usize) -> bool) {
+        let data = b"erasures are cheaper than errors, so twice as much can be missing".repeat(2);
+        let code = QrCode::with_error_correction_level(&data, EcLevel::H).unwrap();
+        let width = code.width();
+        let start = width / 2 - size / 2;
+        let range = start..start + size;
+        let inside = move |i: usize| range.contains(&(i % width)) && range.contains(&(i / width));
+        (data, code, inside)
+    }
+
+    #[test]
+    fn test_partial_matrix() {
+        let (data, code, inside) = covered_symbol(12);
+        let modules = code
+            .to_colors()
+            .into_iter()
+            .enumerate()
+            .map(|(i, color)| (!inside(i)).then_some(color))
+            .collect::<Vec<_>>();
+        let decoded = decode_partial_matrix(&modules, code.width()).unwrap();
+        assert_eq!(decoded.data, data);
+        assert!(decoded.erasures.iter().sum::<usize>() > 0);
+        assert!(decoded.corrections.iter().all(|c| *c == 0));
+    }
+
+    #[test]
+    fn test_partial_matrix_with_error() {
+        let (data, code, inside) = covered_symbol(12);
+        let last = code.width() * code.width() - 1;
+        let modules = code
+            .to_colors()
+            .into_iter()
+            .enumerate()
+            // The bottom right module belongs to the first data codeword.
+            .map(|(i, color)| if i == last { Some(!color) } else { (!inside(i)).then_some(color) })
+            .collect::<Vec<_>>();
+        let decoded = decode_partial_matrix(&modules, code.width()).unwrap();
+        assert_eq!(decoded.data, data);
+        assert_eq!(decoded.corrections.iter().sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn test_erasures_beyond_error_capacity() {
+        let (data, code, inside) = covered_symbol(30);
+        let colors = code.to_colors();
+        let flipped = colors
+            .iter()
+            .enumerate()
+            .map(|(i, color)| if inside(i) { !*color } else { *color })
+            .collect::<Vec<_>>();
+        assert_eq!(decode_matrix(&flipped, code.width()), Err(QrError::TooManyErrors));
+
+        let erased = colors
+            .iter()
+            .enumerate()
+            .map(|(i, color)| (!inside(i)).then_some(*color))
+            .collect::<Vec<_>>();
+        assert_eq!(decode_partial_matrix(&erased, code.width()).unwrap().data, data);
+    }
+
+    /// A 1-L symbol with the first `count` data codewords corrupted.
+    fn corrupted_1l(count: usize) -> Vec<Color> {
+        let mut codewords = EncodedBits::new(b"HELLO", EcLevel::L)
+            .unwrap()
+            .into_codewords()
+            .unwrap();
+        for codeword in &mut codewords.data_block_mut(0)[..count] {
+            *codeword ^= 0xa5;
+        }
+        let code = codewords.place().mask(&IsoPenalty).into_qr_code();
+        assert_eq!(code.version(), Version::Normal(1));
+        code.to_colors()
+    }
+
+    #[test]
+    fn test_misdecode_protection() {
+        // 1-L has 7 error correction codewords, 3 of which are set aside for
+        // misdecode protection, which leaves room for 2 errors.
+        assert_eq!(decode_matrix(&corrupted_1l(2), 21).unwrap().data, b"HELLO");
+        assert_eq!(decode_matrix(&corrupted_1l(3), 21), Err(QrError::TooManyErrors));
+    }
+
+    #[test]
+    fn test_unknown_matrix() {
+        let modules = vec![None; 25 * 25];
+        assert!(decode_partial_matrix(&modules, 25).is_err());
+    }
+
+    #[test]
+    fn test_invalid_width() {
+        assert!(decode_matrix(&[Color::Light; 22 * 22], 22).is_err());
+        assert!(decode_matrix(&[Color::Light; 21 * 20], 21).is_err());
+    }
+
+    fn transpose<T: Copy>(modules: &[T], width: usize) -> Vec<T> {
+        (0..width * width).map(|i| modules[(i % width) * width + i / width]).collect()
+    }
+
+    #[test]
+    fn test_mirrored() {
+        for (data, ec_level) in [(&b"mirror"[..], EcLevel::M), (&[b'7'; 900][..], EcLevel::Q)] {
+            let code = QrCode::with_error_correction_level(data, ec_level).unwrap();
+            let width = code.width();
+            let decoded = decode_matrix(&transpose(&code.to_colors(), width), width).unwrap();
+            assert!(decoded.mirrored);
+            assert_eq!(decoded.data, data);
+            assert_eq!(decoded.ideal_matrix(), code.to_colors());
+        }
+    }
+
+    #[test]
+    fn test_mirrored_partial_matrix() {
+        let (data, code, inside) = covered_symbol(12);
+        let width = code.width();
+        let modules = code
+            .to_colors()
+            .into_iter()
+            .enumerate()
+            .map(|(i, color)| (!inside(i)).then_some(color))
+            .collect::<Vec<_>>();
+        let decoded = decode_partial_matrix(&transpose(&modules, width), width).unwrap();
+        assert!(decoded.mirrored);
+        assert_eq!(decoded.data, data);
+    }
+}
@@ -0,0 +1,439 @@
+//! Synthetic degradation of rendered symbols, to test how robust scanners
+//! are.
+//!
+//! A `Degradation` imitates one thing which goes wrong between printing a
+//! symbol and capturing it again: blur, sensor noise, JPEG artefacts,
+//! perspective, rotation, uneven lighting, ink spreading or shrinking, and
+//! damaged modules. Degradations are applied in order, drawing whatever they
+//! need at random from a seeded `Rng`, so every image of a set can be
+//! reproduced from the seed and the parameters recorded in its manifest. The
+//! manifest records the values drawn as well, e.g. the actual angle of a
+//! rotation.
+
+use std::f64::consts::PI;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{imageops, GrayImage, ImageFormat, ImageResult, Luma};
+use serde_json::{json, Value};
+
+use crate::scan::sample::Perspective;
+use crate::scan::Point;
+
+/// A small seedable pseudo-random number generator (SplitMix64). It is not
+/// suitable for anything but reproducible test data.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub const fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly distributed number in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// A uniformly distributed number in [low, high).
+    pub fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.next_f64()
+    }
+
+    /// A uniformly distributed integer in [0, n).
+    pub fn below(&mut self, n: u32) -> u32 {
+        (self.next_f64() * f64::from(n)) as u32
+    }
+
+    /// A normally distributed number with mean 0 and standard deviation 1.
+    pub fn gaussian(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
+
+/// One kind of degradation and its parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Degradation {
+    /// Gaussian blur with the given standard deviation, in pixels.
+    Blur { sigma: f32 },
+
+    /// Additive Gaussian noise with the given standard deviation, in grey
+    /// levels.
+    Noise { sigma: f64 },
+
+    /// A round trip through a JPEG encoder at the given quality (1 to 100).
+    Jpeg { quality: u8 },
+
+    /// Moves every corner of the image by up to `strength` times its size in
+    /// a random direction, and warps the image accordingly.
+    Perspective { strength: f64 },
+
+    /// Rotation around the centre of the image, by up to `max_degrees` in
+    /// either direction. The image grows to the bounding box of the rotated
+    /// image, so that nothing is lost; the new corners are light.
+    Rotation { max_degrees: f64 },
+
+    /// Darkens the image along a random direction, by `strength` (0 to 1) at
+    /// the darkest edge.
+    Lighting { strength: f64 },
+
+    /// Grows dark areas by `pixels` in every direction, or shrinks them if
+    /// negative.
+    InkSpread { pixels: i32 },
+
+    /// Inverts `count` random cells of a grid of `module_size` pixels,
+    /// starting at the top-left corner. This hits whole modules if it is
+    /// applied first to an image from `Renderer`, whose quiet zone is a whole
+    /// number of modules wide.
+    ModuleDamage { count: u32, module_size: u32 },
+}
+
+impl Degradation {
+    /// Applies the degradation, returning an image of the same size, except
+    /// for `Rotation`.
+    pub fn apply(&self, image: &GrayImage, rng: &mut Rng) -> GrayImage {
+        self.apply_recorded(image, rng).0
+    }
+
+    /// Like `apply`, but also returns the values drawn from `rng` as a JSON
+    /// object, or `None` if the degradation draws nothing or only per-pixel
+    /// noise.
+    pub fn apply_recorded(&self, image: &GrayImage, rng: &mut Rng) -> (GrayImage, Option<Value>) {
+        match *self {
+            Self::Blur { sigma } => (imageops::blur(image, sigma), None),
+            Self::Noise { sigma } => {
+                let mut image = image.clone();
+                for pixel in image.pixels_mut() {
+                    let value = f64::from(pixel[0]) + rng.gaussian() * sigma;
+                    pixel[0] = value.round().clamp(0.0, 255.0) as u8;
+                }
+                (image, None)
+            }
+            Self::Jpeg { quality } => {
+                let mut bytes = Vec::new();
+                JpegEncoder::new_with_quality(&mut bytes, quality.clamp(1, 100))
+                    .encode_image(image)
+                    .expect("encoding to memory");
+                let image = image::load(Cursor::new(bytes), ImageFormat::Jpeg)
+                    .expect("decoding what was just encoded")
+                    .to_luma8();
+                (image, None)
+            }
+            Self::Perspective { strength } => {
+                let (w, h) = (f64::from(image.width()), f64::from(image.height()));
+                let corners = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)];
+                let moved = corners.map(|(x, y)| {
+                    let angle = rng.range(0.0, 2.0 * PI);
+                    let distance = rng.range(0.0, strength) * w.max(h);
+                    Point::new(x + distance * angle.cos(), y + distance * angle.sin())
+                });
+                let (width, height) = image.dimensions();
+                let drawn = json!({ "corners": moved.map(|p| [p.x, p.y]) });
+                let from = corners.map(|(x, y)| Point::new(x, y));
+                let image = warp(image, from, moved, width, height);
+                (image, Some(drawn))
+            }
+            Self::Rotation { max_degrees } => {
+                let degrees = rng.range(-max_degrees, max_degrees);
+                let angle = degrees.to_radians();
+                let (w, h) = (f64::from(image.width()), f64::from(image.height()));
+                let corners = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)];
+                let (cx, cy, cos, sin) = (w / 2.0, h / 2.0, angle.cos(), angle.sin());
+                // Rotated around the centre of the bounding box of the result.
+                let (new_w, new_h) = (
+                    (w * cos.abs() + h * sin.abs()).ceil(),
+                    (w * sin.abs() + h * cos.abs()).ceil(),
+                );
+                let rotated = corners.map(|(x, y)| {
+                    Point::new(
+                        new_w / 2.0 + (x - cx) * cos - (y - cy) * sin,
+                        new_h / 2.0 + (x - cx) * sin + (y - cy) * cos,
+                    )
+                });
+                let from = corners.map(|(x, y)| Point::new(x, y));
+                let image = warp(image, from, rotated, new_w as u32, new_h as u32);
+                (image, Some(json!({ "degrees": degrees })))
+            }
+            Self::Lighting { strength } => {
+                let angle = rng.range(0.0, 2.0 * PI);
+                let (dx, dy) = (angle.cos(), angle.sin());
+                let (w, h) = (f64::from(image.width()), f64::from(image.height()));
+                let project = |x: f64, y: f64| x * dx + y * dy;
+                let ends = [project(0.0, 0.0), project(w, 0.0), project(0.0, h), project(w, h)];
+                let low = ends.iter().copied().fold(f64::MAX, f64::min);
+                let high = ends.iter().copied().fold(f64::MIN, f64::max);
+                let image = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+                    let t = (project(f64::from(x) + 0.5, f64::from(y) + 0.5) - low) / (high - low);
+                    let value = f64::from(image.get_pixel(x, y)[0]) * (1.0 - strength * t);
+                    Luma([value.round().clamp(0.0, 255.0) as u8])
+                });
+                (image, Some(json!({ "direction_degrees": angle.to_degrees() })))
+            }
+            Self::InkSpread { pixels } => {
+                let radius = i64::from(pixels.unsigned_abs());
+                let (w, h) = (i64::from(image.width()), i64::from(image.height()));
+                let image = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+                    let neighbours = (-radius..=radius).flat_map(|dy| {
+                        (-radius..=radius).filter_map(move |dx| {
+                            let (nx, ny) = (i64::from(x) + dx, i64::from(y) + dy);
+                            ((0..w).contains(&nx) && (0..h).contains(&ny))
+                                .then(|| image.get_pixel(nx as u32, ny as u32)[0])
+                        })
+                    });
+                    // Spreading ink makes every pixel as dark as its darkest
+                    // neighbour.
+                    let value = if pixels > 0 { neighbours.min() } else { neighbours.max() };
+                    Luma([value.unwrap_or(255)])
+                });
+                (image, None)
+            }
+            Self::ModuleDamage { count, module_size } => {
+                let mut image = image.clone();
+                let module_size = module_size.max(1);
+                let (columns, rows) = (image.width() / module_size, image.height() / module_size);
+                if columns == 0 || rows == 0 {
+                    return (image, None);
+                }
+                let mut cells = Vec::new();
+                for _ in 0..count {
+                    let (column, row) = (rng.below(columns), rng.below(rows));
+                    cells.push([column, row]);
+                    for y in row * module_size..(row + 1) * module_size {
+                        for x in column * module_size..(column + 1) * module_size {
+                            let pixel = image.get_pixel_mut(x, y);
+                            pixel[0] = 255 - pixel[0];
+                        }
+                    }
+                }
+                (image, Some(json!({ "cells": cells })))
+            }
+        }
+    }
+
+    /// The degradation as a JSON object with a `kind` and its parameters.
+    pub fn to_json(&self) -> Value {
+        match *self {
+            Self::Blur { sigma } => json!({ "kind": "blur", "sigma": sigma }),
+            Self::Noise { sigma } => json!({ "kind": "noise", "sigma": sigma }),
+            Self::Jpeg { quality } => json!({ "kind": "jpeg", "quality": quality }),
+            Self::Perspective { strength } => {
+                json!({ "kind": "perspective", "strength": strength })
+            }
+            Self::Rotation { max_degrees } => {
+                json!({ "kind": "rotation", "max_degrees": max_degrees })
+            }
+            Self::Lighting { strength } => json!({ "kind": "lighting", "strength": strength }),
+            Self::InkSpread { pixels } => json!({ "kind": "ink_spread", "pixels": pixels }),
+            Self::ModuleDamage { count, module_size } => {
+                json!({ "kind": "module_damage", "count": count, "module_size": module_size })
+            }
+        }
+    }
+}
+
+/// Maps the quadrilateral `from` of `image` onto `to` in an image of
+/// `width × height` pixels. Pixels with no source are light.
+fn warp(image: &GrayImage, from: [Point; 4], to: [Point; 4], width: u32, height: u32) -> GrayImage {
+    let inverse = Perspective::from_quads(to, from);
+    GrayImage::from_fn(width, height, |x, y| {
+        let p = inverse.map(Point::new(f64::from(x) + 0.5, f64::from(y) + 0.5));
+        Luma([bilinear(image, p.x - 0.5, p.y - 0.5)])
+    })
+}
+
+/// Interpolates between the four pixels around (x, y), given in pixel
+/// indices. Pixels outside the image are light.
+fn bilinear(image: &GrayImage, x: f64, y: f64) -> u8 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let pixel = |x: f64, y: f64| {
+        if x < 0.0 || y < 0.0 || x >= f64::from(image.width()) || y >= f64::from(image.height()) {
+            255.0
+        } else {
+            f64::from(image.get_pixel(x as u32, y as u32)[0])
+        }
+    };
+    let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+    let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+    (top * (1.0 - fy) + bottom * fy).round() as u8
+}
+
+/// A degraded image to generate: the degradations to apply, in order, and
+/// the seed of their random choices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub seed: u64,
+    pub degradations: Vec<Degradation>,
+}
+
+impl Case {
+    pub fn apply(&self, image: &GrayImage) -> GrayImage {
+        self.apply_recorded(image).0
+    }
+
+    /// Like `apply`, but also returns what every degradation drew, see
+    /// `Degradation::apply_recorded`.
+    pub fn apply_recorded(&self, image: &GrayImage) -> (GrayImage, Vec<Option<Value>>) {
+        let mut rng = Rng::new(self.seed);
+        let mut drawn = Vec::with_capacity(self.degradations.len());
+        let mut image = image.clone();
+        for degradation in &self.degradations {
+            let (degraded, values) = degradation.apply_recorded(&image, &mut rng);
+            image = degraded;
+            drawn.push(values);
+        }
+        (image, drawn)
+    }
+
+    /// Draws a case with every kind of degradation included at random, with
+    /// moderate parameters, in the order they happen in practice: damage and
+    /// ink on the print, then geometry and lighting at capture, then the
+    /// camera's blur, noise and compression.
+    pub fn random(rng: &mut Rng, module_size: u32) -> Self {
+        let size = f64::from(module_size);
+        let spread = (rng.range(-0.25, 0.25) * size).round() as i32;
+        let candidates = [
+            Degradation::ModuleDamage { count: 1 + rng.below(20), module_size },
+            Degradation::InkSpread { pixels: spread },
+            Degradation::Perspective { strength: rng.range(0.0, 0.08) },
+            Degradation::Rotation { max_degrees: rng.range(0.0, 180.0) },
+            Degradation::Lighting { strength: rng.range(0.0, 0.6) },
+            Degradation::Blur { sigma: rng.range(0.3, 0.3 * size) as f32 },
+            Degradation::Noise { sigma: rng.range(0.0, 25.0) },
+            Degradation::Jpeg { quality: 20 + rng.below(70) as u8 },
+        ];
+        let degradations = candidates
+            .into_iter()
+            .filter(|d| rng.next_f64() < 0.5 && *d != Degradation::InkSpread { pixels: 0 })
+            .collect();
+        Self {
+            seed: rng.next_u64(),
+            degradations,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "seed": self.seed,
+            "degradations": self.degradations.iter().map(Degradation::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Saves the degraded images as `NN.png` in `dir`, together with a
+/// `manifest.json` recording the encoded data (in hex, and as text if it is
+/// UTF-8) and every case with the values its degradations drew, and returns
+/// the path of the manifest.
+pub fn write_set(
+    image: &GrayImage,
+    data: &[u8],
+    cases: &[Case],
+    dir: &Path,
+) -> ImageResult<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let mut entries = Vec::with_capacity(cases.len());
+    for (i, case) in cases.iter().enumerate() {
+        let file = format!("{i:02}.png");
+        let (degraded, drawn) = case.apply_recorded(image);
+        degraded.save(dir.join(&file))?;
+        let mut entry = case.to_json();
+        let degradations = entry["degradations"].as_array_mut().expect("a list");
+        for (degradation, drawn) in degradations.iter_mut().zip(drawn) {
+            if let Some(drawn) = drawn {
+                degradation["drawn"] = drawn;
+            }
+        }
+        entry["file"] = json!(file);
+        // Rotation enlarges the image.
+        entry["width"] = json!(degraded.width());
+        entry["height"] = json!(degraded.height());
+        entries.push(entry);
+    }
+    let manifest = json!({
+        "data_hex": data.iter().map(|byte| format!("{byte:02x}")).collect::<String>(),
+        "text": std::str::from_utf8(data).ok(),
+        "width": image.width(),
+        "height": image.height(),
+        "cases": entries,
+    });
+    let path = dir.join("manifest.json");
+    std::fs::write(&path, serde_json::to_string_pretty(&manifest).expect("valid JSON"))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qr_code::QrCode;
+
+    fn sample_image() -> GrayImage {
+        let code = QrCode::new(b"degraded").unwrap();
+        code.render::<Luma<u8>>().module_dimensions(4, 4).build()
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let image = sample_image();
+        let mut rng = Rng::new(42);
+        let cases = (0..10).map(|_| Case::random(&mut rng, 4)).collect::<Vec<_>>();
+        let mut rng = Rng::new(42);
+        for case in cases {
+            assert_eq!(case, Case::random(&mut rng, 4));
+            let (first, drawn) = case.apply_recorded(&image);
+            let (second, drawn_again) = case.apply_recorded(&image);
+            assert_eq!(first.as_raw(), second.as_raw());
+            assert_eq!(drawn, drawn_again);
+            assert_eq!(drawn.len(), case.degradations.len());
+        }
+    }
+
+    #[test]
+    fn test_recorded_values() {
+        let image = sample_image();
+        let rotation = Degradation::Rotation { max_degrees: 30.0 };
+        let (rotated, drawn) = rotation.apply_recorded(&image, &mut Rng::new(7));
+        // The recorded angle is the first value drawn, and apply gives the
+        // same image.
+        let degrees = drawn.unwrap()["degrees"].as_f64().unwrap();
+        assert_eq!(Rng::new(7).range(-30.0, 30.0), degrees);
+        assert_eq!(rotation.apply(&image, &mut Rng::new(7)), rotated);
+
+        let mut rng = Rng::new(7);
+        let damage = Degradation::ModuleDamage { count: 3, module_size: 4 };
+        let (_, drawn) = damage.apply_recorded(&image, &mut rng);
+        assert_eq!(drawn.unwrap()["cells"].as_array().unwrap().len(), 3);
+        assert_eq!(Degradation::Blur { sigma: 1.0 }.apply_recorded(&image, &mut rng).1, None);
+    }
+
+    #[test]
+    fn test_manifest() {
+        let dir = std::env::temp_dir().join(format!("qr_code_degrade_{}", std::process::id()));
+        let data = [0xff, 0x00, b'a'];
+        let cases = [Case {
+            seed: 1,
+            degradations: vec![Degradation::Rotation { max_degrees: 10.0 }],
+        }];
+        let path = write_set(&sample_image(), &data, &cases, &dir).unwrap();
+        let manifest = std::fs::read_to_string(path).unwrap();
+        let manifest: Value = serde_json::from_str(&manifest).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(manifest["data_hex"], "ff0061");
+        assert_eq!(manifest["text"], Value::Null);
+        let rotation = &manifest["cases"][0]["degradations"][0];
+        assert_eq!(rotation["kind"], "rotation");
+        assert!(rotation["drawn"]["degrees"].as_f64().unwrap().abs() < 10.0);
+    }
+}
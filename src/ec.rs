@@ -1,5 +1,7 @@
+use std::cmp::Ordering;
+use std::fmt;
 use std::ops::Deref;
-use crate::types::{EcLevel, QrResult, Version};
+use crate::types::{EcLevel, QrError, QrResult, Version};
 
 static GENERATOR_POLYNOMIALS: [&[u8]; 70] = [
     b"",
@@ -378,7 +380,7 @@ static DATA_BYTES_PER_BLOCK: [[(usize, usize, usize, usize); 4]; 44] = [
     [(16, 1, 0, 0), (14, 1, 0, 0), (10, 1, 0, 0), (0, 0, 0, 0)], // M4
 ];
 
-fn interleave<T: Copy, V: Deref<Target = [T]>>(blocks: &[V]) -> Vec<T> {
+pub fn interleave<T: Copy, V: Deref<Target = [T]>>(blocks: &[V]) -> Vec<T> {
     let last_block_len = blocks.last().expect("non-empty blocks").len();
     let mut res = Vec::with_capacity(last_block_len * blocks.len());
     for i in 0..last_block_len {
@@ -391,33 +393,95 @@ fn interleave<T: Copy, V: Deref<Target = [T]>>(blocks: &[V]) -> Vec<T> {
     res
 }
 
+/// Reverses `interleave`, splitting the codewords into blocks of the given
+/// lengths.
+pub fn deinterleave<T: Copy>(codewords: &[T], lengths: &[usize]) -> Vec<Vec<T>> {
+    let mut blocks = lengths.iter().map(|len| Vec::with_capacity(*len)).collect::<Vec<_>>();
+    let mut codewords = codewords.iter();
+    let longest = lengths.iter().copied().max().unwrap_or(0);
+    for i in 0..longest {
+        for (block, len) in blocks.iter_mut().zip(lengths) {
+            if i < *len {
+                block.extend(codewords.next());
+            }
+        }
+    }
+    blocks
+}
+
 pub fn create_error_correction_code(data: &[u8], ec_code_size: usize) -> Vec<u8> {
-    let data_len = data.len();
-    let log_den = GENERATOR_POLYNOMIALS[ec_code_size];
+    divide_by_generator(data, ec_code_size, |_, _| {})
+}
 
+/// The generator polynomial of `ec_code_size` error correction codewords,
+/// without its leading term, multiplied by `α^log`.
+fn scaled_generator(ec_code_size: usize, log: u8) -> impl Iterator<Item = u8> {
+    GENERATOR_POLYNOMIALS[ec_code_size]
+        .iter()
+        .map(move |v| EXP_TABLE[(usize::from(*v) + usize::from(log)) % 255])
+}
+
+/// Divides the data, followed by `ec_code_size` zeros, by the generator
+/// polynomial and returns the remainder, i.e. the error correction code.
+/// `on_step` is called after each step with the leading coefficient it
+/// eliminated and the rest of the working polynomial.
+fn divide_by_generator<F: FnMut(u8, &[u8])>(
+    data: &[u8],
+    ec_code_size: usize,
+    mut on_step: F,
+) -> Vec<u8> {
+    let data_len = data.len();
     let mut res = data.to_vec();
     res.resize(ec_code_size + data_len, 0);
 
     for i in 0..data_len {
-        let lead_coeff = res[i] as usize;
-        if lead_coeff == 0 {
-            continue;
-        }
-
-        let log_lead_coeff = usize::from(LOG_TABLE[lead_coeff]);
-        for (u, v) in res[i + 1..].iter_mut().zip(log_den.iter()) {
-            *u ^= EXP_TABLE[(usize::from(*v) + log_lead_coeff) % 255];
+        let lead_coeff = res[i];
+        if lead_coeff != 0 {
+            let generator = scaled_generator(ec_code_size, LOG_TABLE[usize::from(lead_coeff)]);
+            for (u, v) in res[i + 1..].iter_mut().zip(generator) {
+                *u ^= v;
+            }
         }
+        on_step(lead_coeff, &res[i + 1..]);
     }
 
     res.split_off(data_len)
 }
 
-pub fn construct_codewords(
+/// Returns the number of data codewords in each block, and the number of
+/// error correction codewords which every block carries.
+pub fn block_layout(version: Version, ec_level: EcLevel) -> QrResult<(Vec<usize>, usize)> {
+    let (block_1_size, block_1_count, block_2_size, block_2_count) =
+        version.fetch(ec_level, &DATA_BYTES_PER_BLOCK)?;
+    let ec_bytes = version.fetch(ec_level, &EC_BYTES_PER_BLOCK)?;
+    let mut lengths = vec![block_1_size; block_1_count];
+    lengths.resize(block_1_count + block_2_count, block_2_size);
+    Ok((lengths, ec_bytes))
+}
+
+/// The number of error correction codewords which ISO/IEC 18004 sets aside
+/// for misdecode protection in the smallest symbols. They do not count
+/// towards the correction capacity.
+pub const fn misdecode_protection(version: Version, ec_level: EcLevel) -> usize {
+    match (version, ec_level) {
+        (Version::Normal(1), EcLevel::L) => 3,
+        (Version::Normal(1), EcLevel::M) | (Version::Normal(2), EcLevel::L) => 2,
+        (Version::Normal(1), _) | (Version::Normal(3), EcLevel::L) => 1,
+        _ => 0,
+    }
+}
+
+/// A list of codeword blocks, in the order defined by `DATA_BYTES_PER_BLOCK`.
+pub type Blocks = Vec<Vec<u8>>;
+
+/// Splits the raw data into blocks and computes the error correction code of
+/// each block, without interleaving them. The data must fill the capacity of
+/// the version exactly.
+pub fn construct_blocks(
     rawbits: &[u8],
     version: Version,
     ec_level: EcLevel,
-) -> QrResult<(Vec<u8>, Vec<u8>)> {
+) -> QrResult<(Blocks, Blocks)> {
     let (block_1_size, block_1_count, block_2_size, block_2_count) =
         version.fetch(ec_level, &DATA_BYTES_PER_BLOCK)?;
 
@@ -425,13 +489,17 @@ pub fn construct_codewords(
     let block_1_end = block_1_size * block_1_count;
     let total_size = block_1_end + block_2_size * block_2_count;
 
-    debug_assert_eq!(rawbits.len(), total_size);
+    match rawbits.len().cmp(&total_size) {
+        Ordering::Greater => return Err(QrError::DataTooLong),
+        Ordering::Less => return Err(QrError::InvalidDataLength),
+        Ordering::Equal => {}
+    }
 
     // Divide the data into blocks.
     let mut blocks = Vec::with_capacity(blocks_count);
-    blocks.extend(rawbits[..block_1_end].chunks(block_1_size));
+    blocks.extend(rawbits[..block_1_end].chunks(block_1_size).map(<[u8]>::to_vec));
     if block_2_size > 0 {
-        blocks.extend(rawbits[block_1_end..].chunks(block_2_size));
+        blocks.extend(rawbits[block_1_end..].chunks(block_2_size).map(<[u8]>::to_vec));
     }
 
     // Generate EC codes.
@@ -441,8 +509,430 @@ pub fn construct_codewords(
         .map(|block| create_error_correction_code(block, ec_bytes))
         .collect::<Vec<Vec<u8>>>();
 
+    Ok((blocks, ec_codes))
+}
+
+/// One step of the polynomial long division in `create_error_correction_code`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RsStep {
+    /// The leading coefficient which is eliminated in this step.
+    pub lead_coefficient: u8,
+
+    /// The discrete logarithm of `lead_coefficient`, i.e. the exponent `e`
+    /// with `α^e == lead_coefficient`. `None` if the coefficient is zero, in
+    /// which case the step is skipped.
+    pub lead_log: Option<u8>,
+
+    /// The generator polynomial multiplied by `α^lead_log`, without its
+    /// leading term. This is XORed into the working polynomial.
+    pub scaled_generator: Vec<u8>,
+
+    /// The working polynomial after the step. Its last `ec_code_size`
+    /// coefficients become the error correction code after the final step.
+    pub remainder: Vec<u8>,
+}
+
+/// A record of how the error correction code of a block is computed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RsTrace {
+    /// The data codewords of the block.
+    pub data: Vec<u8>,
+
+    /// The generator polynomial, as exponents of α, without the leading term
+    /// (which is always `α^0`).
+    pub generator_logs: Vec<u8>,
+
+    /// One entry per data codeword.
+    pub steps: Vec<RsStep>,
+
+    /// The resulting error correction code.
+    pub ec: Vec<u8>,
+}
+
+/// Same as `create_error_correction_code`, but records every division step.
+pub fn trace_error_correction_code(data: &[u8], ec_code_size: usize) -> RsTrace {
+    let mut steps = Vec::with_capacity(data.len());
+    let ec = divide_by_generator(data, ec_code_size, |lead_coefficient, remainder| {
+        let lead_log = (lead_coefficient != 0).then(|| LOG_TABLE[usize::from(lead_coefficient)]);
+        steps.push(RsStep {
+            lead_coefficient,
+            lead_log,
+            scaled_generator: match lead_log {
+                Some(log) => scaled_generator(ec_code_size, log).collect(),
+                None => vec![0; ec_code_size],
+            },
+            remainder: remainder.to_vec(),
+        });
+    });
+
+    RsTrace {
+        data: data.to_vec(),
+        generator_logs: GENERATOR_POLYNOMIALS[ec_code_size].to_vec(),
+        steps,
+        ec,
+    }
+}
+
+impl fmt::Display for RsTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn hex(bytes: &[u8]) -> String {
+            bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")
+        }
+
+        writeln!(f, "data:      {}", hex(&self.data))?;
+        let logs = self.generator_logs.iter().map(u8::to_string).collect::<Vec<_>>();
+        writeln!(f, "generator: α^0 + α^({}) (exponents)", logs.join(", "))?;
+        writeln!(f, "step  lead  log  generator × α^log / remainder")?;
+        for (i, step) in self.steps.iter().enumerate() {
+            match step.lead_log {
+                Some(log) => {
+                    writeln!(
+                        f,
+                        "{i:>4}  0x{:02x}  {log:>3}  {}",
+                        step.lead_coefficient,
+                        hex(&step.scaled_generator)
+                    )?;
+                }
+                None => writeln!(f, "{i:>4}  0x00    -  (skipped)")?,
+            }
+            writeln!(f, "{:>16}{}", "", hex(&step.remainder))?;
+        }
+        write!(f, "ec:        {}", hex(&self.ec))
+    }
+}
+
+pub fn construct_codewords(
+    rawbits: &[u8],
+    version: Version,
+    ec_level: EcLevel,
+) -> QrResult<(Vec<u8>, Vec<u8>)> {
+    let (blocks, ec_codes) = construct_blocks(rawbits, version, ec_level)?;
+
     let blocks_vec = interleave(&blocks);
     let ec_vec = interleave(&ec_codes);
 
     Ok((blocks_vec, ec_vec))
-}
\ No newline at end of file
+}
+
+//------------------------------------------------------------------------------
+// Decoding
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        0
+    } else {
+        let (log_a, log_b) = (LOG_TABLE[usize::from(a)], LOG_TABLE[usize::from(b)]);
+        gf_exp(usize::from(log_a) + usize::from(log_b))
+    }
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    debug_assert_ne!(b, 0, "division by zero in GF(256)");
+    if a == 0 {
+        0
+    } else {
+        let (log_a, log_b) = (LOG_TABLE[usize::from(a)], LOG_TABLE[usize::from(b)]);
+        gf_exp(usize::from(log_a) + 255 - usize::from(log_b))
+    }
+}
+
+/// Returns α^power.
+fn gf_exp(power: usize) -> u8 {
+    EXP_TABLE[power % 255]
+}
+
+/// Evaluates a polynomial whose coefficients are given from the lowest
+/// degree up.
+fn poly_eval(poly: &[u8], x: u8) -> u8 {
+    poly.iter().rev().fold(0, |acc, c| gf_mul(acc, x) ^ c)
+}
+
+/// Multiplies two polynomials whose coefficients are given from the lowest
+/// degree up.
+fn poly_mul(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut res = vec![0; a.len() + b.len() - 1];
+    for (i, x) in a.iter().enumerate() {
+        for (j, y) in b.iter().enumerate() {
+            res[i + j] ^= gf_mul(*x, *y);
+        }
+    }
+    res
+}
+
+/// Computes the syndromes `S_j = r(α^j)` for `j` in `0..ec_len`, where the
+/// first codeword of `block` is the highest degree coefficient of `r`.
+fn syndromes(block: &[u8], ec_len: usize) -> Vec<u8> {
+    (0..ec_len)
+        .map(|j| {
+            let x = gf_exp(j);
+            block.iter().fold(0, |acc, c| gf_mul(acc, x) ^ c)
+        })
+        .collect()
+}
+
+/// Corrects errors and erasures in a block made of data codewords followed by
+/// `ec_len` error correction codewords, in place.
+///
+/// `erasures` lists the indices of codewords which are known to be unreliable.
+/// A block can be recovered as long as `2 × errors + erasures ≤ ec_len`.
+///
+/// Returns the number of codewords which were changed. Fails with
+/// `QrError::TooManyErrors` if the block cannot be recovered, in which case
+/// `block` is left untouched.
+pub fn correct_errors(block: &mut [u8], ec_len: usize, erasures: &[usize]) -> QrResult<usize> {
+    let n = block.len();
+    assert!(ec_len <= n, "the block is shorter than its error correction code");
+    let synd = syndromes(block, ec_len);
+    if synd.iter().all(|s| *s == 0) {
+        return Ok(0);
+    }
+
+    let mut erasures = erasures.to_vec();
+    erasures.sort_unstable();
+    erasures.dedup();
+    if erasures.len() > ec_len || erasures.iter().any(|i| *i >= n) {
+        return Err(QrError::TooManyErrors);
+    }
+
+    // The codeword at index `i` is the coefficient of x^(n-1-i), so its
+    // locator is α^(n-1-i).
+    let locator_of = |i: usize| gf_exp(n - 1 - i);
+
+    // Erasure locator Γ(x) = ∏ (1 + X_k x).
+    let erasure_locator = erasures
+        .iter()
+        .fold(vec![1], |acc, i| poly_mul(&acc, &[1, locator_of(*i)]));
+
+    // Berlekamp–Massey, initialized with the erasure locator, yields the
+    // errata locator Λ(x) of both errors and erasures.
+    let e = erasures.len();
+    let mut lambda = erasure_locator.clone();
+    let mut prev = erasure_locator;
+    let mut len = e;
+    let mut shift = 1;
+    let mut prev_discrepancy = 1;
+    for k in e..ec_len {
+        let discrepancy = (0..=len.min(k))
+            .filter(|i| *i < lambda.len())
+            .fold(0, |acc, i| acc ^ gf_mul(lambda[i], synd[k - i]));
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+        let scale = gf_div(discrepancy, prev_discrepancy);
+        let mut next = lambda.clone();
+        next.resize(next.len().max(prev.len() + shift), 0);
+        for (i, c) in prev.iter().enumerate() {
+            next[i + shift] ^= gf_mul(scale, *c);
+        }
+        if 2 * len <= k + e {
+            prev = std::mem::replace(&mut lambda, next);
+            len = k + 1 + e - len;
+            prev_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            lambda = next;
+            shift += 1;
+        }
+    }
+    while lambda.len() > 1 && lambda.last() == Some(&0) {
+        lambda.pop();
+    }
+    let degree = lambda.len() - 1;
+    if degree != len || 2 * degree > ec_len + e {
+        return Err(QrError::TooManyErrors);
+    }
+
+    // Chien search: position i is in error if Λ(X_i⁻¹) = 0.
+    let positions = (0..n)
+        .filter(|i| poly_eval(&lambda, gf_div(1, locator_of(*i))) == 0)
+        .collect::<Vec<_>>();
+    if positions.len() != degree {
+        return Err(QrError::TooManyErrors);
+    }
+
+    // Forney: e_k = X_k Ω(X_k⁻¹) / Λ'(X_k⁻¹), with Ω(x) = S(x) Λ(x) mod x^ec_len.
+    let mut omega = poly_mul(&synd, &lambda);
+    omega.truncate(ec_len);
+    let derivative = lambda
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, c)| if i % 2 == 1 { *c } else { 0 })
+        .collect::<Vec<_>>();
+    let mut corrected = block.to_vec();
+    let mut changed = 0;
+    for i in positions {
+        let x = locator_of(i);
+        let x_inv = gf_div(1, x);
+        let denominator = poly_eval(&derivative, x_inv);
+        if denominator == 0 {
+            return Err(QrError::TooManyErrors);
+        }
+        let magnitude = gf_mul(x, gf_div(poly_eval(&omega, x_inv), denominator));
+        if magnitude != 0 {
+            corrected[i] ^= magnitude;
+            changed += 1;
+        }
+    }
+
+    if syndromes(&corrected, ec_len).iter().any(|s| *s != 0) {
+        return Err(QrError::TooManyErrors);
+    }
+    block.copy_from_slice(&corrected);
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cast::As;
+
+    /// A data block of `data_len` codewords followed by its error correction
+    /// code, filled from a simple linear congruential generator.
+    fn codeword(data_len: usize, ec_len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        let mut data = (0..data_len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+        let ec = create_error_correction_code(&data, ec_len);
+        data.extend(ec);
+        data
+    }
+
+    /// `count` distinct positions spread over a block of length `n`, which
+    /// must not be a multiple of 37.
+    fn positions(n: usize, count: usize, offset: usize) -> Vec<usize> {
+        assert!(count <= n && !n.is_multiple_of(37));
+        (0..count).map(|i| (offset + i * 37) % n).collect()
+    }
+
+    #[test]
+    fn test_no_errors() {
+        let original = codeword(16, 10, 1);
+        let mut block = original.clone();
+        assert_eq!(correct_errors(&mut block, 10, &[]), Ok(0));
+        assert_eq!(block, original);
+    }
+
+    #[test]
+    fn test_max_errors() {
+        for (data_len, ec_len) in [(16, 10), (19, 7), (97, 30), (225, 30)] {
+            for seed in 0..20 {
+                let original = codeword(data_len, ec_len, seed);
+                let mut block = original.clone();
+                let n = block.len();
+                for p in positions(n, ec_len / 2, seed as usize) {
+                    block[p] ^= 0x5a;
+                }
+                assert_eq!(correct_errors(&mut block, ec_len, &[]), Ok(ec_len / 2));
+                assert_eq!(block, original);
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_erasures() {
+        for (data_len, ec_len) in [(16, 10), (19, 7), (97, 30)] {
+            for seed in 0..20 {
+                let original = codeword(data_len, ec_len, seed);
+                let mut block = original.clone();
+                let erasures = positions(block.len(), ec_len, seed as usize);
+                for p in &erasures {
+                    block[*p] = 0;
+                }
+                let changed = erasures.iter().filter(|p| original[**p] != 0).count();
+                assert_eq!(correct_errors(&mut block, ec_len, &erasures), Ok(changed));
+                assert_eq!(block, original);
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors_and_erasures() {
+        let (data_len, ec_len) = (44, 26);
+        for errors in 0..=ec_len / 2 {
+            let original = codeword(data_len, ec_len, errors.as_u32());
+            let mut block = original.clone();
+            let wrong = positions(block.len(), ec_len - errors, errors);
+            let (corrupted, erasures) = wrong.split_at(errors);
+            for p in corrupted {
+                block[*p] ^= 0xff;
+            }
+            for p in erasures {
+                block[*p] ^= 0x01;
+            }
+            assert!(2 * corrupted.len() + erasures.len() <= ec_len);
+            assert_eq!(correct_errors(&mut block, ec_len, erasures), Ok(wrong.len()));
+            assert_eq!(block, original);
+        }
+    }
+
+    #[test]
+    fn test_too_many_errors() {
+        for (data_len, ec_len) in [(16, 10), (97, 30)] {
+            for seed in 0..20 {
+                let mut block = codeword(data_len, ec_len, seed);
+                for p in positions(block.len(), ec_len, seed as usize) {
+                    block[p] ^= 0x33;
+                }
+                let damaged = block.clone();
+                assert_eq!(correct_errors(&mut block, ec_len, &[]), Err(QrError::TooManyErrors));
+                assert_eq!(block, damaged);
+            }
+        }
+    }
+
+    #[test]
+    fn test_too_many_erasures() {
+        let mut block = codeword(16, 10, 3);
+        block[0] ^= 1;
+        let damaged = block.clone();
+        let erasures = (0..11).collect::<Vec<_>>();
+        assert_eq!(correct_errors(&mut block, 10, &erasures), Err(QrError::TooManyErrors));
+        assert_eq!(block, damaged);
+    }
+
+    #[test]
+    fn test_errors_beyond_erasure_capacity() {
+        let (data_len, ec_len) = (44, 26);
+        let mut block = codeword(data_len, ec_len, 9);
+        let wrong = positions(block.len(), 20, 0);
+        for p in &wrong {
+            block[*p] ^= 0x80;
+        }
+        // 10 errors and 10 erasures need 2 × 10 + 10 = 30 > 26 codewords.
+        let damaged = block.clone();
+        assert_eq!(correct_errors(&mut block, ec_len, &wrong[10..]), Err(QrError::TooManyErrors));
+        assert_eq!(block, damaged);
+    }
+
+    #[test]
+    fn test_trace_matches_create() {
+        for (data_len, ec_len) in [(1, 7), (16, 10), (19, 7), (97, 30), (118, 30)] {
+            let block = codeword(data_len, ec_len, 3);
+            let data = &block[..data_len];
+            let trace = trace_error_correction_code(data, ec_len);
+            assert_eq!(trace.ec, create_error_correction_code(data, ec_len));
+            assert_eq!(trace.ec, &block[data_len..]);
+            assert_eq!(trace.data, data);
+            assert_eq!(trace.generator_logs.len(), ec_len);
+            assert_eq!(trace.steps.len(), data_len);
+            for step in &trace.steps {
+                assert_eq!(step.scaled_generator.len(), ec_len);
+                assert_eq!(step.lead_log.is_some(), step.lead_coefficient != 0);
+            }
+            assert_eq!(trace.steps[0].lead_coefficient, data[0]);
+        }
+
+        // A zero leading coefficient skips the step.
+        let trace = trace_error_correction_code(&[0, 0x40, 0], 10);
+        assert_eq!(trace.steps.len(), 3);
+        assert_eq!(trace.steps[0].lead_log, None);
+        assert_eq!(trace.steps[0].scaled_generator, [0; 10]);
+        assert_eq!(trace.ec, create_error_correction_code(&[0, 0x40, 0], 10));
+    }
+}
@@ -0,0 +1,20 @@
+pub mod analysis;
+pub mod bch;
+pub mod bits;
+mod cast;
+pub mod canvas;
+pub mod decode;
+pub mod degrade;
+pub mod ec;
+pub mod mask;
+pub mod optimize;
+pub mod parse;
+pub mod pipeline;
+pub mod provenance;
+pub mod qr_code;
+pub mod render;
+pub mod report;
+pub mod scan;
+pub mod types;
+pub mod validate;
+pub mod walkthrough;
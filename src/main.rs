@@ -1,20 +1,145 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
 use image::Luma;
-use crate::qr_code::QrCode;
+use qr_code::analysis::EcBudget;
+use qr_code::degrade::{self, Case, Rng};
+use qr_code::pipeline::EncodedBits;
+use qr_code::qr_code::QrCode;
+use qr_code::render::gif::{self, GifOptions};
+use qr_code::report;
+use qr_code::scan;
+use qr_code::scan::binarize::{Binarizer, HybridBlockMean, Otsu, Sauvola};
+use qr_code::scan::grade;
+use qr_code::types::EcLevel;
+use qr_code::walkthrough;
 
-mod qr_code;
-mod bits;
-mod types;
-mod optimize;
-mod cast;
-mod ec;
-mod canvas;
-mod render;
+mod tui;
 
 fn main() {
-    let code = QrCode::new(b"http://www.baidu.com").unwrap();
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(String::as_str) {
+        // qr_code walkthrough <data> [output directory]
+        Some("walkthrough") => {
+            let data = args.get(1).expect("missing data to encode");
+            let dir = args.get(2).map_or("walkthrough", String::as_str);
+            let frames = walkthrough::frames(data.as_bytes(), EcLevel::M).unwrap();
+            for path in walkthrough::write_frames(&frames, Path::new(dir)).unwrap() {
+                println!("{}", path.display());
+            }
+        }
+        // qr_code gif <data> [output file]
+        Some("gif") => {
+            let data = args.get(1).expect("missing data to encode");
+            let path = args.get(2).map_or("placement.gif", String::as_str);
+            let placed = EncodedBits::new(data.as_bytes(), EcLevel::M)
+                .and_then(EncodedBits::into_codewords)
+                .unwrap()
+                .place();
+            let file = BufWriter::new(File::create(path).unwrap());
+            gif::write_placement_gif(&placed, file, &GifOptions::default()).unwrap();
+        }
+        // qr_code report <data> [output file]
+        Some("report") => {
+            let data = args.get(1).expect("missing data to encode");
+            let path = args.get(2).map_or("report.html", String::as_str);
+            let html = report::html_report(data.as_bytes(), EcLevel::M).unwrap();
+            fs::write(path, html).unwrap();
+        }
+        // qr_code heatmap <data> [output file]
+        Some("heatmap") => {
+            let data = args.get(1).expect("missing data to encode");
+            let path = args.get(2).map_or("heatmap.png", String::as_str);
+            let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M).unwrap();
+            let budget = EcBudget::new(code.version(), EcLevel::M).unwrap();
+            println!(
+                "{:?}: {} blocks of {:?} data codewords, each correcting {} errors or {} erasures",
+                code.version(),
+                budget.data_lengths.len(),
+                budget.data_lengths,
+                budget.correctable_errors(),
+                budget.correctable_erasures(),
+            );
+            let heat = budget.heat_map(5);
+            budget.render_heat_map(&heat, 8).save(path).unwrap();
+        }
+        // qr_code degrade <data> [output directory] [count] [seed]
+        Some("degrade") => {
+            let data = args.get(1).expect("missing data to encode");
+            let dir = Path::new(args.get(2).map_or("degraded", String::as_str));
+            let count = args.get(3).map_or(Ok(20), |n| n.parse()).expect("invalid count");
+            let seed = args.get(4).map_or(Ok(0), |n| n.parse()).expect("invalid seed");
+            let module_size = 6;
+            let image = QrCode::new(data.as_bytes())
+                .unwrap()
+                .render::<Luma<u8>>()
+                .module_dimensions(module_size, module_size)
+                .build();
+            let mut rng = Rng::new(seed);
+            let cases = (0..count)
+                .map(|_| Case::random(&mut rng, module_size))
+                .collect::<Vec<_>>();
+            let manifest = degrade::write_set(&image, data.as_bytes(), &cases, dir).unwrap();
+            println!("{}", manifest.display());
+        }
+        Some("tui") => tui::run().unwrap(),
+        // qr_code binarize <image> [output directory]
+        Some("binarize") => {
+            let input = args.get(1).expect("missing image to binarize");
+            let dir = Path::new(args.get(2).map_or("binarized", String::as_str));
+            let image = image::open(input).unwrap().to_luma8();
+            fs::create_dir_all(dir).unwrap();
+            let methods: [(&str, &dyn Binarizer); 3] = [
+                ("otsu", &Otsu),
+                ("hybrid", &HybridBlockMean),
+                ("sauvola", &Sauvola::default()),
+            ];
+            for (name, binarizer) in methods {
+                let path = dir.join(format!("{name}.png"));
+                binarizer.binarize(&image).to_image().save(&path).unwrap();
+                println!("{}", path.display());
+            }
+        }
+        // qr_code scan <image>
+        Some("scan") => {
+            let input = args.get(1).expect("missing image to scan");
+            let image = image::open(input).unwrap();
+            for result in scan::scan(&image) {
+                let decoded = &result.decoded;
+                println!(
+                    "{:?} {:?} mask {:?}, corrected {:?}, corners {:?}, mirrored {}, inverted {}",
+                    decoded.version,
+                    decoded.ec_level,
+                    decoded.mask_pattern,
+                    decoded.corrections,
+                    result.corners.map(|p| (p.x.round(), p.y.round())),
+                    decoded.mirrored,
+                    result.inverted,
+                );
+                match &result.text {
+                    Some(text) => println!("{text}"),
+                    None => println!("{:02x?}", decoded.data),
+                }
+            }
+        }
+        // qr_code grade <image>
+        Some("grade") => {
+            let input = args.get(1).expect("missing image to grade");
+            let image = image::open(input).unwrap();
+            match grade::grade(&image) {
+                Some(report) => println!("{report}"),
+                None => println!("no symbol found, overall F"),
+            }
+        }
+        _ => {
+            let code = QrCode::new(b"http://www.baidu.com").unwrap();
 
-    let image = code.render::<Luma<u8>>().build();
+            let image = code.render::<Luma<u8>>().build();
 
-    // Save the image.
-    image.save("qrcode.png").unwrap();
+            // Save the image.
+            image.save("qrcode.png").unwrap();
+        }
+    }
 }
@@ -0,0 +1,158 @@
+use crate::canvas::{Canvas, MaskPattern};
+
+/// Decides which mask pattern a symbol should use.
+///
+/// `Canvas::apply_best_mask` applies every pattern to a copy of the canvas and
+/// asks the strategy to score each candidate. The candidate with the lowest
+/// score wins.
+pub trait MaskStrategy {
+    /// Scores a canvas which already has `pattern` applied. Lower is better.
+    fn score(&self, pattern: MaskPattern, canvas: &Canvas) -> u32;
+}
+
+/// The standard penalty rules from ISO/IEC 18004 section 7.8.3. This is the
+/// strategy used when none is given explicitly.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IsoPenalty;
+
+impl MaskStrategy for IsoPenalty {
+    fn score(&self, _: MaskPattern, canvas: &Canvas) -> u32 {
+        u32::from(canvas.compute_total_penalty_scores())
+    }
+}
+
+/// Always selects the same mask pattern, ignoring the content.
+#[derive(Debug, Copy, Clone)]
+pub struct FixedMask(pub MaskPattern);
+
+impl MaskStrategy for FixedMask {
+    fn score(&self, pattern: MaskPattern, _: &Canvas) -> u32 {
+        if pattern == self.0 { 0 } else { u32::MAX }
+    }
+}
+
+/// Combines several strategies into one, summing their scores multiplied by
+/// the given weights.
+#[derive(Default)]
+pub struct Weighted {
+    parts: Vec<(u32, Box<dyn MaskStrategy>)>,
+}
+
+impl Weighted {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a strategy with the given weight.
+    #[must_use]
+    pub fn with<S: MaskStrategy + 'static>(mut self, weight: u32, strategy: S) -> Self {
+        self.parts.push((weight, Box::new(strategy)));
+        self
+    }
+}
+
+impl MaskStrategy for Weighted {
+    fn score(&self, pattern: MaskPattern, canvas: &Canvas) -> u32 {
+        self.parts.iter().fold(0_u32, |total, (weight, strategy)| {
+            total.saturating_add(weight.saturating_mul(strategy.score(pattern, canvas)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::ALL_PATTERNS_QR;
+    use crate::cast::As;
+    use crate::pipeline::EncodedBits;
+    use crate::types::{Color, EcLevel};
+
+    /// The unmasked canvas of `data`.
+    fn unmasked(data: &[u8], ec_level: EcLevel) -> Canvas {
+        EncodedBits::new(data, ec_level)
+            .and_then(EncodedBits::into_codewords)
+            .unwrap()
+            .place()
+            .canvas()
+            .clone()
+    }
+
+    fn payloads() -> Vec<(Vec<u8>, EcLevel)> {
+        vec![
+            (b"01234567".to_vec(), EcLevel::M),
+            (b"HELLO WORLD".to_vec(), EcLevel::Q),
+            (b"mask strategies".to_vec(), EcLevel::L),
+            (b"a somewhat longer payload to reach version 7 or so".repeat(3), EcLevel::H),
+        ]
+    }
+
+    /// Scores by the number of dark modules.
+    struct DarkModules;
+
+    impl MaskStrategy for DarkModules {
+        fn score(&self, _: MaskPattern, canvas: &Canvas) -> u32 {
+            let colors = canvas.clone().into_colors();
+            colors.iter().filter(|c| **c == Color::Dark).count().as_u32()
+        }
+    }
+
+    #[test]
+    fn test_fixed_mask() {
+        let canvas = unmasked(b"01234567", EcLevel::M);
+        for pattern in ALL_PATTERNS_QR {
+            let (selected, masked) = canvas.select_mask(&FixedMask(pattern));
+            assert_eq!(selected, pattern);
+            let mut expected = canvas.clone();
+            expected.apply_mask(pattern);
+            assert_eq!(masked.into_colors(), expected.into_colors());
+        }
+    }
+
+    #[test]
+    fn test_iso_penalty_is_the_default_choice() {
+        for (data, ec_level) in payloads() {
+            let canvas = unmasked(&data, ec_level);
+            // The selection before mask strategies existed.
+            let (_, expected) = ALL_PATTERNS_QR
+                .iter()
+                .map(|pattern| {
+                    let mut c = canvas.clone();
+                    c.apply_mask(*pattern);
+                    (c.compute_total_penalty_scores(), c)
+                })
+                .min_by_key(|(score, _)| *score)
+                .unwrap();
+            let masked = canvas.apply_best_mask(&IsoPenalty);
+            assert_eq!(masked.into_colors(), expected.into_colors());
+        }
+    }
+
+    #[test]
+    fn test_weighted() {
+        let mut changed = 0;
+        for (data, ec_level) in payloads() {
+            let canvas = unmasked(&data, ec_level);
+            let (iso, _) = canvas.select_mask(&IsoPenalty);
+            let only_iso = Weighted::new().with(1, IsoPenalty).with(0, DarkModules);
+            assert_eq!(canvas.select_mask(&only_iso).0, iso);
+
+            // With a heavy weight on the dark module count, the choice follows
+            // the weighted sum of both scores.
+            let weighted = Weighted::new().with(1, IsoPenalty).with(1000, DarkModules);
+            let expected = *ALL_PATTERNS_QR
+                .iter()
+                .min_by_key(|pattern| {
+                    let mut c = canvas.clone();
+                    c.apply_mask(**pattern);
+                    IsoPenalty.score(**pattern, &c) + 1000 * DarkModules.score(**pattern, &c)
+                })
+                .unwrap();
+            let (selected, _) = canvas.select_mask(&weighted);
+            assert_eq!(selected, expected);
+            if selected != iso {
+                changed += 1;
+            }
+        }
+        assert!(changed > 0);
+    }
+}
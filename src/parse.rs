@@ -0,0 +1,438 @@
+//! Parsing data codewords back into typed segments.
+//!
+//! This is the mirror image of `Bits::push_segments` and
+//! `Bits::push_terminator`: it walks the bit stream of a symbol's data
+//! codewords and reports every segment, the terminator and the padding which
+//! follows it, together with their positions.
+
+use std::ops::Range;
+
+use crate::cast::{As, Truncate};
+use crate::types::{Mode, QrError, QrResult, Version};
+
+/// The characters of the alphanumeric mode, in value order.
+const ALPHANUMERIC_CHARS: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// The padding codewords, which must alternate in this order.
+const PADDING_BYTES: [u8; 2] = [0b1110_1100, 0b0001_0001];
+
+/// What a segment of the bit stream contains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// Numeric, alphanumeric, byte or Kanji data. Kanji characters are given
+    /// as Shift JIS bytes.
+    Data { mode: Mode, data: Vec<u8> },
+
+    /// An Extended Channel Interpretation designator.
+    Eci(u32),
+
+    /// FNC1 in first position: the data follows the GS1 application
+    /// identifier standard.
+    Fnc1First,
+
+    /// FNC1 in second position, with its application indicator.
+    Fnc1Second(u8),
+
+    /// A Structured Append header: this symbol is number `index` (0-based) of
+    /// `total` symbols, and `parity` is the XOR of all bytes of the message.
+    StructuredAppend { index: u8, total: u8, parity: u8 },
+
+    /// The terminator. It may be shorter than 4 bits if the data codewords
+    /// are full.
+    Terminator,
+}
+
+/// A segment together with the bits it occupies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedSegment {
+    pub kind: SegmentKind,
+
+    /// The bit positions covered, including the mode indicator and the
+    /// character count, counting from the start of the data codewords.
+    pub bits: Range<usize>,
+}
+
+impl ParsedSegment {
+    /// The data codewords the segment touches, at least partially.
+    pub const fn byte_range(&self) -> Range<usize> {
+        self.bits.start / 8..self.bits.end.div_ceil(8)
+    }
+}
+
+/// The padding following the terminator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Padding {
+    /// The bits filling up the byte containing the end of the terminator.
+    pub bits: Range<usize>,
+
+    /// Whether all those bits are zero, as they should be.
+    pub bits_are_zero: bool,
+
+    /// The pad codewords, as a range of byte offsets.
+    pub bytes: Range<usize>,
+
+    /// Whether the pad codewords alternate `0xEC` and `0x11`, as they should.
+    pub bytes_are_standard: bool,
+}
+
+impl Padding {
+    /// Whether the padding is exactly what `Bits::push_terminator` produces.
+    pub const fn is_standard(&self) -> bool {
+        self.bits_are_zero && self.bytes_are_standard
+    }
+}
+
+/// The result of parsing the data codewords.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedStream {
+    pub segments: Vec<ParsedSegment>,
+
+    /// The padding after the terminator. `None` if the segments fill the data
+    /// codewords completely, leaving no room for a terminator.
+    pub padding: Option<Padding>,
+}
+
+impl ParsedStream {
+    /// Concatenates the content of all data segments.
+    pub fn data(&self) -> Vec<u8> {
+        self.segments
+            .iter()
+            .filter_map(|segment| match &segment.kind {
+                SegmentKind::Data { data, .. } => Some(data.as_slice()),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
+
+/// Reads big-endian numbers of arbitrary bit lengths from a byte slice.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    fn read(&mut self, n: usize) -> QrResult<u32> {
+        if n > self.remaining() {
+            return Err(QrError::InvalidBitstream);
+        }
+        let mut res = 0;
+        for i in self.position..self.position + n {
+            res = res << 1 | u32::from(self.data[i / 8] >> (7 - i % 8) & 1);
+        }
+        self.position += n;
+        Ok(res)
+    }
+}
+
+/// Parses the data codewords of a symbol of the given version.
+pub fn parse(data: &[u8], version: Version) -> QrResult<ParsedStream> {
+    let mut reader = BitReader { data, position: 0 };
+    let mut segments = Vec::new();
+    loop {
+        let start = reader.position;
+        let kind = match reader.remaining() {
+            0 => {
+                return Ok(ParsedStream {
+                    segments,
+                    padding: None,
+                })
+            }
+            // A truncated terminator.
+            n @ 1..=3 => match reader.read(n)? {
+                0 => SegmentKind::Terminator,
+                _ => return Err(QrError::InvalidBitstream),
+            },
+            _ => match reader.read(4)? {
+                0b0000 => SegmentKind::Terminator,
+                0b0001 => read_data(&mut reader, Mode::Numeric, version)?,
+                0b0010 => read_data(&mut reader, Mode::Alphanumeric, version)?,
+                0b0100 => read_data(&mut reader, Mode::Byte, version)?,
+                0b1000 => read_data(&mut reader, Mode::Kanji, version)?,
+                0b0111 => SegmentKind::Eci(read_eci_designator(&mut reader)?),
+                0b0101 => SegmentKind::Fnc1First,
+                0b1001 => SegmentKind::Fnc1Second(reader.read(8)?.as_u16().truncate_as_u8()),
+                0b0011 => {
+                    let index = reader.read(4)?.as_u16().truncate_as_u8();
+                    let total = reader.read(4)?.as_u16().truncate_as_u8() + 1;
+                    let parity = reader.read(8)?.as_u16().truncate_as_u8();
+                    SegmentKind::StructuredAppend {
+                        index,
+                        total,
+                        parity,
+                    }
+                }
+                _ => return Err(QrError::InvalidBitstream),
+            },
+        };
+        let is_terminator = kind == SegmentKind::Terminator;
+        segments.push(ParsedSegment {
+            kind,
+            bits: start..reader.position,
+        });
+        if is_terminator {
+            break;
+        }
+    }
+
+    let bits = reader.position..reader.position.next_multiple_of(8);
+    let bits_are_zero = reader.read(bits.len())? == 0;
+    let bytes = reader.position / 8..data.len();
+    let bytes_are_standard = data[bytes.clone()]
+        .iter()
+        .zip(PADDING_BYTES.iter().cycle())
+        .all(|(a, b)| a == b);
+    Ok(ParsedStream {
+        segments,
+        padding: Some(Padding {
+            bits,
+            bits_are_zero,
+            bytes,
+            bytes_are_standard,
+        }),
+    })
+}
+
+/// Reads an ECI designator of 1, 2 or 3 bytes, following the mode indicator.
+fn read_eci_designator(reader: &mut BitReader<'_>) -> QrResult<u32> {
+    let first = reader.read(8)?;
+    match first.as_u16().truncate_as_u8().leading_ones() {
+        0 => Ok(first),
+        1 => Ok((first & 0x3f) << 8 | reader.read(8)?),
+        2 => Ok((first & 0x1f) << 16 | reader.read(16)?),
+        _ => Err(QrError::InvalidBitstream),
+    }
+}
+
+/// Reads the character count and the characters of a data segment.
+fn read_data(reader: &mut BitReader<'_>, mode: Mode, version: Version) -> QrResult<SegmentKind> {
+    let mut count = reader.read(mode.length_bits_count(version))?.as_usize();
+    let mut data = Vec::new();
+    match mode {
+        Mode::Numeric => {
+            while count > 0 {
+                let digits = count.min(3);
+                let value = reader.read(digits * 3 + 1)?;
+                if value >= 10_u32.pow(digits.as_u32()) {
+                    return Err(QrError::InvalidBitstream);
+                }
+                data.extend(format!("{value:0digits$}").bytes());
+                count -= digits;
+            }
+        }
+        Mode::Alphanumeric => {
+            while count > 0 {
+                let chars = count.min(2);
+                let value = reader.read(chars * 5 + 1)?.as_usize();
+                let (first, second) = if chars == 2 {
+                    (value / 45, Some(value % 45))
+                } else {
+                    (value, None)
+                };
+                if first >= 45 {
+                    return Err(QrError::InvalidBitstream);
+                }
+                data.push(ALPHANUMERIC_CHARS[first]);
+                data.extend(second.map(|c| ALPHANUMERIC_CHARS[c]));
+                count -= chars;
+            }
+        }
+        Mode::Byte => {
+            for _ in 0..count {
+                data.push(reader.read(8)?.as_u16().truncate_as_u8());
+            }
+        }
+        Mode::Kanji => {
+            for _ in 0..count {
+                let value = reader.read(13)?.as_u16();
+                let bytes = ((value / 0xc0) << 8) | (value % 0xc0);
+                let cp = if bytes < 0x1f00 { bytes + 0x8140 } else { bytes + 0xc140 };
+                data.extend(cp.to_be_bytes());
+            }
+        }
+    }
+    Ok(SegmentKind::Data { mode, data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Turns a string of `0` and `1`, with spaces for readability, into
+    /// bytes, filling the last byte with zeros and adding the standard pad
+    /// codewords up to `len` bytes.
+    fn stream(bits: &str, len: usize) -> Vec<u8> {
+        let bits = bits.bytes().filter(|b| *b != b' ').collect::<Vec<_>>();
+        let mut bytes = bits
+            .chunks(8)
+            .map(|chunk| {
+                let byte = chunk.iter().fold(0, |acc, b| acc << 1 | u8::from(*b == b'1'));
+                byte << (8 - chunk.len())
+            })
+            .collect::<Vec<_>>();
+        bytes.extend(PADDING_BYTES.iter().cycle().take(len - bytes.len()));
+        bytes
+    }
+
+    fn kinds(stream: &ParsedStream) -> Vec<SegmentKind> {
+        stream.segments.iter().map(|s| s.kind.clone()).collect()
+    }
+
+    fn data(mode: Mode, data: &[u8]) -> SegmentKind {
+        SegmentKind::Data {
+            mode,
+            data: data.to_vec(),
+        }
+    }
+
+    const V1: Version = Version::Normal(1);
+
+    #[test]
+    fn test_data_segments() {
+        // "01234567" in numeric mode, "AC-42" in alphanumeric mode, "点" in
+        // Kanji mode and "hi" in byte mode.
+        let bits = "0001 0000001000 0000001100 0101011001 1000011 \
+                    0010 000000101 00111001110 11100111001 000010 \
+                    1000 00000001 0110110011111 \
+                    0100 00000010 01101000 01101001 0000";
+        let parsed = parse(&stream(bits, 26), V1).unwrap();
+        assert_eq!(
+            kinds(&parsed),
+            [
+                data(Mode::Numeric, b"01234567"),
+                data(Mode::Alphanumeric, b"AC-42"),
+                data(Mode::Kanji, &[0x93, 0x5f]),
+                data(Mode::Byte, b"hi"),
+                SegmentKind::Terminator,
+            ]
+        );
+        assert_eq!(parsed.segments[0].bits, 0..41);
+        assert_eq!(parsed.data(), b"01234567AC-42\x93\x5fhi");
+    }
+
+    #[test]
+    fn test_eci() {
+        // ECI 26 (UTF-8) with a 1-byte designator, then ECI 1000 with a
+        // 2-byte one, before "hi" in byte mode.
+        let bits = "0111 00011010 0111 10000011 11101000 0100 00000010 01101000 01101001 0000";
+        let parsed = parse(&stream(bits, 12), V1).unwrap();
+        assert_eq!(
+            kinds(&parsed),
+            [
+                SegmentKind::Eci(26),
+                SegmentKind::Eci(1000),
+                data(Mode::Byte, b"hi"),
+                SegmentKind::Terminator,
+            ]
+        );
+        assert_eq!(parsed.segments[0].bits, 0..12);
+        assert_eq!(parsed.segments[1].bits, 12..32);
+        assert_eq!(parsed.data(), b"hi");
+    }
+
+    #[test]
+    fn test_fnc1_first() {
+        let bits = "0101 0010 000000010 00111001110 0000";
+        let parsed = parse(&stream(bits, 8), V1).unwrap();
+        assert_eq!(
+            kinds(&parsed),
+            [SegmentKind::Fnc1First, data(Mode::Alphanumeric, b"AC"), SegmentKind::Terminator]
+        );
+        assert_eq!(parsed.segments[0].bits, 0..4);
+    }
+
+    #[test]
+    fn test_fnc1_second() {
+        let bits = "1001 00100101 0001 0000000011 0001111011 0000";
+        let parsed = parse(&stream(bits, 8), V1).unwrap();
+        assert_eq!(
+            kinds(&parsed),
+            [SegmentKind::Fnc1Second(37), data(Mode::Numeric, b"123"), SegmentKind::Terminator]
+        );
+        assert_eq!(parsed.segments[0].bits, 0..12);
+    }
+
+    #[test]
+    fn test_structured_append() {
+        let bits = "0011 0001 0011 10101010 0100 00000001 01111000 0000";
+        let parsed = parse(&stream(bits, 8), V1).unwrap();
+        assert_eq!(
+            kinds(&parsed),
+            [
+                SegmentKind::StructuredAppend {
+                    index: 1,
+                    total: 4,
+                    parity: 0xaa,
+                },
+                data(Mode::Byte, b"x"),
+                SegmentKind::Terminator,
+            ]
+        );
+        assert_eq!(parsed.segments[0].bits, 0..20);
+    }
+
+    #[test]
+    fn test_short_terminator() {
+        // 31 bits of data leave room for a 1-bit terminator only.
+        let parsed = parse(&stream("0001 0000000101 0001111011 0101101", 4), V1).unwrap();
+        assert_eq!(kinds(&parsed), [data(Mode::Numeric, b"12345"), SegmentKind::Terminator]);
+        assert_eq!(parsed.segments[1].bits, 31..32);
+        let padding = parsed.padding.unwrap();
+        assert_eq!((padding.bits.clone(), padding.bytes.clone()), (32..32, 4..4));
+        assert!(padding.bits_are_zero && padding.bytes_are_standard);
+
+        // A truncated terminator must still be zero.
+        let mut data = stream("0001 0000000101 0001111011 0101101", 4);
+        data[3] |= 1;
+        assert_eq!(parse(&data, V1), Err(QrError::InvalidBitstream));
+    }
+
+    #[test]
+    fn test_no_terminator() {
+        // 24 bits of data fill 3 codewords exactly.
+        let parsed = parse(&stream("0001 0000000011 0001111011", 3), V1).unwrap();
+        assert_eq!(kinds(&parsed), [data(Mode::Numeric, b"123")]);
+        assert_eq!(parsed.padding, None);
+    }
+
+    #[test]
+    fn test_padding() {
+        // "x" in byte mode and the terminator end on a byte boundary.
+        let bits = "0100 00000001 01111000 0000";
+        let padding = parse(&stream(bits, 7), V1).unwrap().padding.unwrap();
+        assert_eq!((padding.bits.clone(), padding.bytes.clone()), (24..24, 3..7));
+        assert!(padding.is_standard());
+
+        let mut data = stream(bits, 7);
+        data[4] = 0xec;
+        let padding = parse(&data, V1).unwrap().padding.unwrap();
+        assert!(padding.bits_are_zero && !padding.bytes_are_standard);
+
+        // "1" in numeric mode and the terminator end after 22 bits.
+        let bits = "0001 0000000001 0001 0000";
+        let padding = parse(&stream(bits, 5), V1).unwrap().padding.unwrap();
+        assert_eq!((padding.bits.clone(), padding.bytes.clone()), (22..24, 3..5));
+        assert!(padding.is_standard());
+
+        let mut data = stream(bits, 5);
+        data[2] |= 0x01;
+        let padding = parse(&data, V1).unwrap().padding.unwrap();
+        assert!(!padding.bits_are_zero && padding.bytes_are_standard);
+    }
+
+    #[test]
+    fn test_invalid_mode() {
+        assert_eq!(parse(&stream("1111", 2), V1), Err(QrError::InvalidBitstream));
+    }
+
+    #[test]
+    fn test_truncated_segment() {
+        assert_eq!(parse(&stream("0100 00000101 01111000", 3), V1), Err(QrError::InvalidBitstream));
+    }
+}
@@ -0,0 +1,304 @@
+//! The encoding process split into separate stages.
+//!
+//! `QrCode::with_bits` runs every step in one go. The types here expose each
+//! intermediate result so it can be inspected, modified and then resumed:
+//!
+//! `EncodedBits` → `Codewords` → `PlacedMatrix` → `MaskedSymbol` → `QrCode`
+
+use crate::bits::{self, Bits};
+use crate::canvas::{Canvas, MaskPattern};
+use crate::decode::Decoded;
+use crate::ec;
+use crate::mask::MaskStrategy;
+use crate::qr_code::QrCode;
+use crate::types::{EcLevel, Mismatch, QrError, QrResult, Version};
+
+/// Stage 1: the data bit stream, including the terminator and padding.
+#[derive(Debug)]
+pub struct EncodedBits {
+    bits: Bits,
+    ec_level: EcLevel,
+}
+
+impl EncodedBits {
+    /// Encodes the data with the optimal segmentation and the smallest
+    /// version which fits.
+    pub fn new(data: &[u8], ec_level: EcLevel) -> QrResult<Self> {
+        Ok(Self::from_bits(bits::encode_auto(data, ec_level)?, ec_level))
+    }
+
+    /// Wraps a bit stream built by hand. The caller is responsible for having
+    /// pushed the terminator.
+    pub const fn from_bits(bits: Bits, ec_level: EcLevel) -> Self {
+        Self { bits, ec_level }
+    }
+
+    pub const fn bits(&self) -> &Bits {
+        &self.bits
+    }
+
+    pub const fn bits_mut(&mut self) -> &mut Bits {
+        &mut self.bits
+    }
+
+    pub const fn version(&self) -> Version {
+        self.bits.version()
+    }
+
+    pub const fn error_correction_level(&self) -> EcLevel {
+        self.ec_level
+    }
+
+    /// Splits the bytes into blocks and computes the error correction code of
+    /// each block. Fails unless the bit stream, e.g. after changes through
+    /// `bits_mut`, fills the data capacity of its version exactly.
+    pub fn into_codewords(self) -> QrResult<Codewords> {
+        let version = self.bits.version();
+        let data = self.bits.into_bytes();
+        let (data_blocks, ec_blocks) = ec::construct_blocks(&data, version, self.ec_level)?;
+        Ok(Codewords {
+            version,
+            ec_level: self.ec_level,
+            data_blocks,
+            ec_blocks,
+        })
+    }
+}
+
+/// Stage 2: the data and error correction blocks, before interleaving.
+#[derive(Debug, Clone)]
+pub struct Codewords {
+    version: Version,
+    ec_level: EcLevel,
+    data_blocks: Vec<Vec<u8>>,
+    ec_blocks: Vec<Vec<u8>>,
+}
+
+impl Codewords {
+    pub const fn version(&self) -> Version {
+        self.version
+    }
+
+    pub const fn error_correction_level(&self) -> EcLevel {
+        self.ec_level
+    }
+
+    pub fn data_blocks(&self) -> &[Vec<u8>] {
+        &self.data_blocks
+    }
+
+    /// Gives mutable access to the codewords of a data block, whose length is
+    /// fixed by the version and EC level. The error correction blocks are left
+    /// untouched; call `recompute_error_correction` to bring them back in
+    /// sync, or skip it to deliberately produce a damaged symbol.
+    pub fn data_block_mut(&mut self, block: usize) -> &mut [u8] {
+        &mut self.data_blocks[block]
+    }
+
+    pub fn ec_blocks(&self) -> &[Vec<u8>] {
+        &self.ec_blocks
+    }
+
+    pub fn ec_block_mut(&mut self, block: usize) -> &mut [u8] {
+        &mut self.ec_blocks[block]
+    }
+
+    /// Regenerates every error correction block from its data block.
+    pub fn recompute_error_correction(&mut self) {
+        for (data, ec) in self.data_blocks.iter().zip(&mut self.ec_blocks) {
+            *ec = ec::create_error_correction_code(data, ec.len());
+        }
+    }
+
+    /// Recomputes the error correction code of the given block, recording
+    /// every step of the polynomial division.
+    pub fn trace_error_correction(&self, block: usize) -> ec::RsTrace {
+        ec::trace_error_correction_code(&self.data_blocks[block], self.ec_blocks[block].len())
+    }
+
+    /// The data codewords in the order they are placed in the symbol.
+    pub fn interleaved_data(&self) -> Vec<u8> {
+        ec::interleave(&self.data_blocks)
+    }
+
+    /// The error correction codewords in the order they are placed in the
+    /// symbol, following the data codewords.
+    pub fn interleaved_ec(&self) -> Vec<u8> {
+        ec::interleave(&self.ec_blocks)
+    }
+
+    /// Draws the functional patterns and places the interleaved codewords
+    /// along the zigzag path.
+    pub fn place(self) -> PlacedMatrix {
+        let mut canvas = Canvas::new(self.version, self.ec_level);
+        canvas.draw_all_functional_patterns();
+        canvas.draw_data(&self.interleaved_data(), &self.interleaved_ec());
+        PlacedMatrix {
+            canvas,
+            data_codewords: self.data_blocks.concat(),
+        }
+    }
+}
+
+/// Stage 3: the complete symbol before masking. The format information area
+/// is reserved but still blank.
+#[derive(Clone)]
+pub struct PlacedMatrix {
+    canvas: Canvas,
+
+    /// The data codewords, block after block, for verification.
+    data_codewords: Vec<u8>,
+}
+
+impl PlacedMatrix {
+    pub const fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    pub const fn canvas_mut(&mut self) -> &mut Canvas {
+        &mut self.canvas
+    }
+
+    /// Applies the mask pattern chosen by `strategy`.
+    pub fn mask<S: MaskStrategy + ?Sized>(self, strategy: &S) -> MaskedSymbol {
+        let (pattern, canvas) = self.canvas.select_mask(strategy);
+        MaskedSymbol {
+            canvas,
+            pattern,
+            data_codewords: self.data_codewords,
+        }
+    }
+
+    /// Applies the given mask pattern.
+    pub fn mask_with(mut self, pattern: MaskPattern) -> MaskedSymbol {
+        self.canvas.apply_mask(pattern);
+        MaskedSymbol {
+            canvas: self.canvas,
+            pattern,
+            data_codewords: self.data_codewords,
+        }
+    }
+}
+
+/// Stage 4: the final symbol with mask and format information applied.
+#[derive(Clone)]
+pub struct MaskedSymbol {
+    canvas: Canvas,
+    pattern: MaskPattern,
+    data_codewords: Vec<u8>,
+}
+
+impl MaskedSymbol {
+    pub const fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    pub const fn canvas_mut(&mut self) -> &mut Canvas {
+        &mut self.canvas
+    }
+
+    pub const fn mask_pattern(&self) -> MaskPattern {
+        self.pattern
+    }
+
+    pub fn into_qr_code(self) -> QrCode {
+        QrCode::from_canvas(self.canvas)
+    }
+
+    /// Like `into_qr_code`, but decodes the symbol first and fails unless it
+    /// gives back the data codewords, version, EC level and mask pattern it
+    /// was built from without any correction, see `QrCode::verify`.
+    pub fn into_verified_qr_code(self) -> QrResult<QrCode> {
+        self.into_verified_qr_code_and_decoded().map(|(code, _)| code)
+    }
+
+    /// Like `into_verified_qr_code`, also returning what the symbol decoded
+    /// to, so that callers can check the payload without decoding again.
+    pub(crate) fn into_verified_qr_code_and_decoded(self) -> QrResult<(QrCode, Decoded)> {
+        let code = QrCode::from_canvas(self.canvas);
+        let decoded = code.decode_exactly()?;
+        if decoded.data_codewords != self.data_codewords {
+            return Err(QrError::VerificationFailed(Mismatch::Data));
+        }
+        if decoded.mask_pattern != self.pattern {
+            return Err(QrError::VerificationFailed(Mismatch::MaskPattern));
+        }
+        Ok((code, decoded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mask::IsoPenalty;
+
+    /// `data` in Byte mode at the given version and level.
+    fn encoded(data: &[u8], version: i16, ec_level: EcLevel) -> EncodedBits {
+        let mut bits = Bits::new(Version::Normal(version));
+        bits.push_byte_data(data).unwrap();
+        bits.push_terminator(ec_level).unwrap();
+        EncodedBits::from_bits(bits, ec_level)
+    }
+
+    /// Flips the lowercase bit of the `index`-th byte of a Byte mode payload
+    /// with an 8-bit character count, going through the data block holding
+    /// that bit.
+    fn toggle_case(codewords: &mut Codewords, index: usize) {
+        // The mode indicator and character count take 12 bits, and the case
+        // bit is the third one of the byte.
+        let bit = 12 + 8 * index + 2;
+        let mut byte = bit / 8;
+        let mut block = 0;
+        while byte >= codewords.data_blocks()[block].len() {
+            byte -= codewords.data_blocks()[block].len();
+            block += 1;
+        }
+        codewords.data_block_mut(block)[byte] ^= 0x80 >> (bit % 8);
+    }
+
+    fn decode(codewords: Codewords) -> Decoded {
+        codewords.place().mask(&IsoPenalty).into_qr_code().decode().unwrap()
+    }
+
+    #[test]
+    fn test_edit_data_block() {
+        let mut codewords = encoded(b"pipeline edits", 1, EcLevel::M).into_codewords().unwrap();
+        toggle_case(&mut codewords, 0);
+
+        // Without new error correction, the edit is corrected away.
+        let decoded = decode(codewords.clone());
+        assert_eq!(decoded.data, b"pipeline edits");
+        assert_eq!(decoded.corrections, [1]);
+
+        codewords.recompute_error_correction();
+        let decoded = decode(codewords);
+        assert_eq!(decoded.data, b"Pipeline edits");
+        assert_eq!(decoded.corrections, [0]);
+    }
+
+    #[test]
+    fn test_edit_last_of_several_blocks() {
+        // 5-Q has two blocks of 15 and two of 16 data codewords; character 46
+        // lies in the last one.
+        let data = b"several blocks of data codewords and the last one";
+        let mut codewords = encoded(data, 5, EcLevel::Q).into_codewords().unwrap();
+        assert_eq!(codewords.data_blocks().len(), 4);
+        let before = codewords.data_blocks().to_vec();
+        toggle_case(&mut codewords, 46);
+        assert_eq!(codewords.data_blocks()[..3], before[..3]);
+
+        codewords.recompute_error_correction();
+        let mut expected = data.to_vec();
+        expected[46] = expected[46].to_ascii_uppercase();
+        let decoded = decode(codewords);
+        assert_eq!(decoded.data, expected);
+        assert_eq!(decoded.corrections, [0; 4]);
+    }
+
+    #[test]
+    fn test_short_bit_stream() {
+        let mut encoded = EncodedBits::from_bits(Bits::new(Version::Normal(1)), EcLevel::M);
+        encoded.bits_mut().push_byte_data(b"short").unwrap();
+        assert_eq!(encoded.into_codewords().err(), Some(QrError::InvalidDataLength));
+    }
+}
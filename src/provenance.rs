@@ -0,0 +1,236 @@
+//! Which part of the symbol every module belongs to.
+//!
+//! Data modules are traced back to the block, codeword and bit they carry,
+//! following the same zigzag order as `Canvas::draw_data` and the same
+//! interleaving as `ec::construct_codewords`.
+
+use std::fmt;
+
+use crate::canvas::{
+    alignment_pattern_centers, Canvas, DataModuleIter, Module, FORMAT_INFO_COORDS_QR_MAIN,
+    FORMAT_INFO_COORDS_QR_SIDE, VERSION_INFO_COORDS_BL, VERSION_INFO_COORDS_TR,
+};
+use crate::cast::As;
+use crate::ec;
+use crate::types::{EcLevel, QrResult, Version};
+
+/// Whether a codeword carries data or error correction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CodewordKind {
+    Data,
+    ErrorCorrection,
+}
+
+/// The role of a single module.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ModuleRole {
+    /// One of the three 7×7 position detection patterns.
+    Finder,
+
+    /// The light border around a finder pattern.
+    Separator,
+
+    /// The alternating lines in row 6 and column 6.
+    Timing,
+
+    /// One of the 5×5 alignment patterns.
+    Alignment,
+
+    /// One of the two copies of the 15-bit format information.
+    FormatInfo,
+
+    /// One of the two copies of the 18-bit version information (version 7+).
+    VersionInfo,
+
+    /// The single module at (8, width - 8) which is always dark.
+    DarkModule,
+
+    /// A bit of a data or error correction codeword. `index` counts from the
+    /// start of the block, and `bit` is 7 for the most significant bit.
+    Codeword {
+        kind: CodewordKind,
+        block: usize,
+        index: usize,
+        bit: u8,
+    },
+
+    /// A remainder bit left over after the last codeword.
+    Remainder,
+}
+
+impl ModuleRole {
+    /// Whether the module is part of a function pattern, i.e. not affected by
+    /// masking.
+    pub const fn is_functional(self) -> bool {
+        !matches!(self, Self::Codeword { .. } | Self::Remainder)
+    }
+}
+
+impl fmt::Display for ModuleRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Finder => f.write_str("finder pattern"),
+            Self::Separator => f.write_str("separator"),
+            Self::Timing => f.write_str("timing pattern"),
+            Self::Alignment => f.write_str("alignment pattern"),
+            Self::FormatInfo => f.write_str("format information"),
+            Self::VersionInfo => f.write_str("version information"),
+            Self::DarkModule => f.write_str("dark module"),
+            Self::Codeword {
+                kind,
+                block,
+                index,
+                bit,
+            } => {
+                let kind = match kind {
+                    CodewordKind::Data => "data",
+                    CodewordKind::ErrorCorrection => "EC",
+                };
+                write!(f, "{kind} block {block} codeword {index} bit {bit}")
+            }
+            Self::Remainder => f.write_str("remainder bit"),
+        }
+    }
+}
+
+/// The role of every module of a symbol, in left-to-right, then
+/// top-to-bottom order.
+#[derive(Debug, Clone)]
+pub struct ProvenanceMap {
+    width: i16,
+    roles: Vec<ModuleRole>,
+}
+
+impl ProvenanceMap {
+    pub fn new(version: Version, ec_level: EcLevel) -> QrResult<Self> {
+        let (data_lengths, ec_length) = ec::block_layout(version, ec_level)?;
+        let data_blocks = data_lengths
+            .iter()
+            .enumerate()
+            .map(|(block, len)| (0..*len).map(|index| (block, index)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let ec_blocks = (0..data_lengths.len())
+            .map(|block| (0..ec_length).map(|index| (block, index)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let codewords = ec::interleave(&data_blocks)
+            .into_iter()
+            .map(|(block, index)| (CodewordKind::Data, block, index))
+            .chain(
+                ec::interleave(&ec_blocks)
+                    .into_iter()
+                    .map(|(block, index)| (CodewordKind::ErrorCorrection, block, index)),
+            );
+
+        let width = version.width();
+        let mut canvas = Canvas::new(version, ec_level);
+        canvas.draw_all_functional_patterns();
+        let mut roles = (0..width)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| match canvas.get(x, y) {
+                Module::Empty => ModuleRole::Remainder,
+                _ => classify_functional_module(version, x, y),
+            })
+            .collect::<Vec<_>>();
+
+        let mut coords = DataModuleIter::new(version)
+            .filter(|(x, y)| canvas.get(*x, *y) == Module::Empty);
+        for (kind, block, index) in codewords {
+            for bit in (0..8).rev() {
+                let Some((x, y)) = coords.next() else {
+                    break;
+                };
+                roles[(y * width + x).as_usize()] = ModuleRole::Codeword {
+                    kind,
+                    block,
+                    index,
+                    bit,
+                };
+            }
+        }
+
+        Ok(Self { width, roles })
+    }
+
+    pub const fn width(&self) -> i16 {
+        self.width
+    }
+
+    /// Returns the role of the module at (x, y).
+    pub fn get(&self, x: i16, y: i16) -> ModuleRole {
+        self.roles[(y * self.width + x).as_usize()]
+    }
+
+    pub fn roles(&self) -> &[ModuleRole] {
+        &self.roles
+    }
+
+    /// Returns the coordinates of the 8 modules holding the given codeword,
+    /// from the most significant bit to the least.
+    pub fn codeword_modules(
+        &self,
+        kind: CodewordKind,
+        block: usize,
+        index: usize,
+    ) -> Vec<(i16, i16)> {
+        let mut modules = self
+            .roles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, role)| match *role {
+                ModuleRole::Codeword {
+                    kind: k,
+                    block: b,
+                    index: n,
+                    bit,
+                } if (k, b, n) == (kind, block, index) => Some((bit, i)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        modules.sort_by_key(|(bit, _)| std::cmp::Reverse(*bit));
+        let width = self.width.as_usize();
+        modules
+            .into_iter()
+            .map(|(_, i)| ((i % width).as_i16(), (i / width).as_i16()))
+            .collect()
+    }
+}
+
+/// Classifies a module which `Canvas::draw_all_functional_patterns` has
+/// drawn.
+fn classify_functional_module(version: Version, x: i16, y: i16) -> ModuleRole {
+    let width = version.width();
+    let normalize = |(cx, cy): (i16, i16)| {
+        (
+            if cx < 0 { cx + width } else { cx },
+            if cy < 0 { cy + width } else { cy },
+        )
+    };
+
+    if (x, y) == (8, width - 8) {
+        return ModuleRole::DarkModule;
+    }
+    for &(fx, fy) in &[(0, 0), (width - 7, 0), (0, width - 7)] {
+        if (fx..fx + 7).contains(&x) && (fy..fy + 7).contains(&y) {
+            return ModuleRole::Finder;
+        }
+        if (fx - 1..fx + 8).contains(&x) && (fy - 1..fy + 8).contains(&y) {
+            return ModuleRole::Separator;
+        }
+    }
+    let on = |coords: &[(i16, i16)]| coords.iter().any(|c| normalize(*c) == (x, y));
+    if on(&FORMAT_INFO_COORDS_QR_MAIN) || on(&FORMAT_INFO_COORDS_QR_SIDE) {
+        return ModuleRole::FormatInfo;
+    }
+    if matches!(version, Version::Normal(7..))
+        && (on(&VERSION_INFO_COORDS_BL) || on(&VERSION_INFO_COORDS_TR))
+    {
+        return ModuleRole::VersionInfo;
+    }
+    if alignment_pattern_centers(version)
+        .iter()
+        .any(|(cx, cy)| (x - cx).abs() <= 2 && (y - cy).abs() <= 2)
+    {
+        return ModuleRole::Alignment;
+    }
+    ModuleRole::Timing
+}
@@ -1,7 +1,35 @@
-use crate::{bits, canvas, ec};
+use crate::bits;
+use crate::canvas::Canvas;
 use crate::cast::As;
+use crate::decode::{self, Decoded};
+use crate::mask::{IsoPenalty, MaskStrategy};
+use crate::pipeline::{EncodedBits, MaskedSymbol};
 use crate::render::{Pixel, Renderer};
-use crate::types::{Color, EcLevel, QrResult, Version};
+use crate::types::{Color, EcLevel, Mismatch, QrError, QrResult, Version};
+
+/// Options of `QrCode::with_options` and `QrCode::with_bits_and_options`.
+#[derive(Clone, Copy)]
+pub struct EncodeOptions<'a> {
+    pub ec_level: EcLevel,
+
+    /// Chooses the mask pattern.
+    pub mask_strategy: &'a dyn MaskStrategy,
+
+    /// Whether to decode the finished symbol and fail with
+    /// `QrError::VerificationFailed` unless it gives back what it was built
+    /// from, see `QrCode::verify`. Meant as a safety net before printing.
+    pub verify: bool,
+}
+
+impl Default for EncodeOptions<'_> {
+    fn default() -> Self {
+        Self {
+            ec_level: EcLevel::L,
+            mask_strategy: &IsoPenalty,
+            verify: false,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct QrCode {
@@ -21,10 +49,36 @@ impl QrCode{
         ec_level: EcLevel,
     ) -> QrResult<Self> {
         let bits = bits::encode_auto(data.as_ref(), ec_level)?;
-        println!("bits: {:?}", bits);
         Self::with_bits(bits, ec_level)
     }
 
+    /// Like `with_error_correction_level`, but decodes the finished symbol
+    /// and fails with `QrError::VerificationFailed` unless it gives back
+    /// `data`. Meant as a safety net before printing.
+    pub fn with_verification<D: AsRef<[u8]>>(data: D, ec_level: EcLevel) -> QrResult<Self> {
+        let options = EncodeOptions {
+            ec_level,
+            verify: true,
+            ..EncodeOptions::default()
+        };
+        Self::with_options(data, &options)
+    }
+
+    /// Encodes `data` in the most compact way, as configured by `options`.
+    pub fn with_options<D: AsRef<[u8]>>(data: D, options: &EncodeOptions) -> QrResult<Self> {
+        let bits = bits::encode_auto(data.as_ref(), options.ec_level)?;
+        let symbol = Self::masked_symbol(bits, options)?;
+        if !options.verify {
+            return Ok(symbol.into_qr_code());
+        }
+        // A single decode checks both the codewords and the payload.
+        let (code, decoded) = symbol.into_verified_qr_code_and_decoded()?;
+        if decoded.data != data.as_ref() {
+            return Err(QrError::VerificationFailed(Mismatch::Data));
+        }
+        Ok(code)
+    }
+
     /**
      * 根据已编码的位序列创建QR码
      * 
@@ -39,24 +93,161 @@ impl QrCode{
      * 这是QR码生成的核心函数，处理从位序列到最终QR码图像的转换过程
      */
     pub fn with_bits(bits: bits::Bits, ec_level: EcLevel) -> QrResult<Self> {
-        let version = bits.version();
-        let data = bits.into_bytes();
-        let (encoded_data, ec_data) = ec::construct_codewords(&data, version, ec_level)?;
-        println!("encoded_data: {:?}", encoded_data);
-        println!("ec_data: {:?}", ec_data);
-        let mut canvas = canvas::Canvas::new(version, ec_level);
-        canvas.draw_all_functional_patterns();
-        canvas.draw_data(&encoded_data, &ec_data);
-        let canvas = canvas.apply_best_mask();
-        Ok(Self {
+        Self::with_bits_and_mask_strategy(bits, ec_level, &IsoPenalty)
+    }
+
+    /// Like `with_bits`, but lets `strategy` choose the mask pattern instead
+    /// of the standard penalty score.
+    pub fn with_bits_and_mask_strategy<S: MaskStrategy + ?Sized>(
+        bits: bits::Bits,
+        ec_level: EcLevel,
+        strategy: &S,
+    ) -> QrResult<Self> {
+        Ok(EncodedBits::from_bits(bits, ec_level)
+            .into_codewords()?
+            .place()
+            .mask(strategy)
+            .into_qr_code())
+    }
+
+    /// Like `with_bits`, as configured by `options`. With `options.verify`
+    /// the symbol has to give back the data codewords of `bits`.
+    pub fn with_bits_and_options(bits: bits::Bits, options: &EncodeOptions) -> QrResult<Self> {
+        let symbol = Self::masked_symbol(bits, options)?;
+        if options.verify {
+            symbol.into_verified_qr_code()
+        } else {
+            Ok(symbol.into_qr_code())
+        }
+    }
+
+    fn masked_symbol(bits: bits::Bits, options: &EncodeOptions) -> QrResult<MaskedSymbol> {
+        Ok(EncodedBits::from_bits(bits, options.ec_level)
+            .into_codewords()?
+            .place()
+            .mask(options.mask_strategy))
+    }
+
+    /// Wraps a finished canvas, e.g. the result of the staged pipeline in
+    /// `crate::pipeline`.
+    pub fn from_canvas(canvas: Canvas) -> Self {
+        let version = canvas.version();
+        let ec_level = canvas.error_correction_level();
+        Self {
             content: canvas.into_colors(),
             version,
             ec_level,
             width: version.width().as_usize(),
-        })
+        }
+    }
+    /// Decodes a module matrix, e.g. the output of `to_colors`, back into the
+    /// data it encodes.
+    pub fn decode_matrix(colors: &[Color], width: usize) -> QrResult<Decoded> {
+        decode::decode_matrix(colors, width)
+    }
+    /// Decodes a module matrix in which some modules are unknown (`None`),
+    /// treating the codewords they belong to as erasures.
+    pub fn decode_partial_matrix(modules: &[Option<Color>], width: usize) -> QrResult<Decoded> {
+        decode::decode_partial_matrix(modules, width)
+    }
+    /// Decodes this symbol.
+    pub fn decode(&self) -> QrResult<Decoded> {
+        decode::decode_matrix(&self.content, self.width)
+    }
+    /// Checks that the symbol decodes to `data` at its own version and error
+    /// correction level, without needing any correction or mirroring.
+    /// Decoding errors are returned as they are, anything else that differs
+    /// as `QrError::VerificationFailed`.
+    pub fn verify(&self, data: &[u8]) -> QrResult<()> {
+        if self.decode_exactly()?.data != data {
+            return Err(QrError::VerificationFailed(Mismatch::Data));
+        }
+        Ok(())
+    }
+    /// Decodes this symbol, failing unless it decodes at its own version and
+    /// error correction level without any correction or mirroring.
+    pub(crate) fn decode_exactly(&self) -> QrResult<Decoded> {
+        let decoded = self.decode()?;
+        let mismatch = if decoded.version != self.version {
+            Mismatch::Version
+        } else if decoded.ec_level != self.ec_level {
+            Mismatch::EcLevel
+        } else if decoded.corrections.iter().any(|n| *n != 0) {
+            Mismatch::Corrections
+        } else if decoded.mirrored {
+            Mismatch::Mirrored
+        } else {
+            return Ok(decoded);
+        };
+        Err(QrError::VerificationFailed(mismatch))
+    }
+    pub const fn version(&self) -> Version {
+        self.version
+    }
+    pub const fn error_correction_level(&self) -> EcLevel {
+        self.ec_level
+    }
+    pub const fn width(&self) -> usize {
+        self.width
     }
-    pub fn render<P: Pixel>(&self) -> Renderer<P> {
+    pub fn to_colors(&self) -> Vec<Color> {
+        self.content.clone()
+    }
+    pub fn render<P: Pixel>(&self) -> Renderer<'_, P> {
         let quiet_zone = 4;
         Renderer::new(&self.content, self.width, quiet_zone)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::{MaskPattern, Module};
+    use crate::mask::FixedMask;
+
+    #[test]
+    fn test_verify_all_paths() {
+        let data = b"01234567";
+        for ec_level in [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H] {
+            let code = QrCode::with_verification(data, ec_level).unwrap();
+            assert_eq!(code.verify(data), Ok(()));
+
+            let options = EncodeOptions {
+                ec_level,
+                mask_strategy: &FixedMask(MaskPattern::Checkerboard),
+                verify: true,
+            };
+            assert!(QrCode::with_options(data, &options).is_ok());
+            let bits = bits::encode_auto(data, ec_level).unwrap();
+            assert!(QrCode::with_bits_and_options(bits, &options).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_verify_mismatch() {
+        let code = QrCode::new(b"01234567").unwrap();
+        assert_eq!(
+            code.verify(b"0123456"),
+            Err(QrError::VerificationFailed(Mismatch::Data))
+        );
+    }
+
+    #[test]
+    fn test_verified_pipeline_rejects_corrections() {
+        let bits = bits::encode_auto(b"01234567", EcLevel::M).unwrap();
+        let mut symbol = EncodedBits::from_bits(bits, EcLevel::M)
+            .into_codewords()
+            .unwrap()
+            .place()
+            .mask(&IsoPenalty);
+        // The bottom right corner holds the first data codeword.
+        let Module::Masked(color) = symbol.canvas().get(20, 20) else {
+            panic!("the symbol is masked");
+        };
+        symbol.canvas_mut().put(20, 20, !color);
+        assert_eq!(
+            symbol.clone().into_verified_qr_code().err(),
+            Some(QrError::VerificationFailed(Mismatch::Corrections))
+        );
+        assert!(symbol.into_qr_code().decode().is_ok());
+    }
+}
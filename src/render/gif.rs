@@ -0,0 +1,259 @@
+//! Animated GIF of the order in which codewords are placed in the symbol.
+//!
+//! Every frame adds one or more codewords along the zigzag path of
+//! `DataModuleIter`. Consecutive codewords alternate between two colours so
+//! their boundaries are visible, data codewords use blue and error correction
+//! codewords use orange, and the most recently placed codewords are red.
+//! After the first frame, only the area around the codewords which changed
+//! colour is written, which keeps large symbols small and fast to encode.
+//! This uses the `gif` crate directly, since the `GifEncoder` of `image` drops
+//! frame offsets and disposes of every frame.
+
+use std::borrow::Cow;
+use std::io::Write;
+
+use gif::{DisposalMethod, Encoder, EncodingError, Frame, Repeat};
+use image::error::{
+    EncodingError as ImageEncodingError, ImageFormatHint, ParameterError, ParameterErrorKind,
+};
+use image::{imageops, ImageError, ImageFormat, ImageResult, Rgba, RgbaImage};
+
+use crate::canvas::{DataModuleIter, Module};
+use crate::cast::As;
+use crate::pipeline::PlacedMatrix;
+use crate::provenance::{CodewordKind, ModuleRole, ProvenanceMap};
+use crate::render::Renderer;
+use crate::types::Color;
+
+/// The number of frames aimed for when `GifOptions::codewords_per_frame` is
+/// `None`.
+const TARGET_FRAMES: usize = 100;
+
+/// Options of `write_placement_gif`.
+#[derive(Debug, Clone, Copy)]
+pub struct GifOptions {
+    /// The number of codewords added in each frame, or `None` to add as many
+    /// as it takes to show the whole symbol in about 100 frames.
+    pub codewords_per_frame: Option<usize>,
+
+    /// How long each frame is shown, in milliseconds.
+    pub frame_delay_ms: u32,
+
+    /// How long the completed symbol is shown before looping, in milliseconds.
+    pub final_delay_ms: u32,
+
+    /// The size of a module in pixels.
+    pub module_size: u32,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self {
+            codewords_per_frame: None,
+            frame_delay_ms: 80,
+            final_delay_ms: 2000,
+            module_size: 8,
+        }
+    }
+}
+
+/// Writes an animated GIF showing how the codewords of `placed` are laid out.
+pub fn write_placement_gif<W: Write>(
+    placed: &PlacedMatrix,
+    writer: W,
+    options: &GifOptions,
+) -> ImageResult<()> {
+    let canvas = placed.canvas();
+    let version = canvas.version();
+    let map = ProvenanceMap::new(version, canvas.error_correction_level())
+        .expect("a canvas always has a valid version");
+    let width = version.width().as_usize();
+
+    // The codeword number (in placement order) of every data module.
+    let mut order = vec![None; width * width];
+    let mut codewords_count = 0_usize;
+    let mut last = None;
+    for (x, y) in DataModuleIter::new(version) {
+        let role = map.get(x, y);
+        if let ModuleRole::Codeword {
+            kind, block, index, ..
+        } = role
+        {
+            if last != Some((kind, block, index)) {
+                last = Some((kind, block, index));
+                codewords_count += 1;
+            }
+            order[y.as_usize() * width + x.as_usize()] = Some(codewords_count - 1);
+        }
+    }
+
+    let colors = canvas.clone().into_colors();
+    let mut renderer = Renderer::<Rgba<u8>>::new(&colors, width, 4);
+    renderer.module_dimensions(options.module_size, options.module_size);
+    let pixel = |module: usize| (module + 4).as_u32() * options.module_size;
+    let Ok(image_size) = u16::try_from(pixel(width + 4)) else {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::DimensionMismatch,
+        )));
+    };
+
+    let mut encoder = Encoder::new(writer, image_size, image_size, &[]).map_err(encoding_error)?;
+    encoder.set_repeat(Repeat::Infinite).map_err(encoding_error)?;
+    let step = options
+        .codewords_per_frame
+        .unwrap_or_else(|| codewords_count.div_ceil(TARGET_FRAMES))
+        .max(1);
+    let mut previous_newest = 0;
+    let mut placed_count = 0;
+    while placed_count < codewords_count {
+        let newest = placed_count;
+        placed_count = (placed_count + step).min(codewords_count);
+        let image = renderer.build_with(|i, color| {
+            let (x, y) = ((i % width).as_i16(), (i / width).as_i16());
+            match order[i] {
+                Some(n) if n >= placed_count => Rgba([200, 200, 200, 255]),
+                Some(n) => codeword_color(map.get(x, y), n, n >= newest, color),
+                None if canvas.get(x, y) == Module::Empty => Rgba([200, 200, 200, 255]),
+                None => Rgba(color.select([90, 90, 90, 255], [255, 255, 255, 255])),
+            }
+        });
+
+        // The codewords placed by the previous frame lose their highlight,
+        // and those placed by this one gain it; nothing else changes.
+        let (left, top, image) = if newest == 0 {
+            (0, 0, image)
+        } else {
+            let changed = order
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| n.is_some_and(|n| (previous_newest..placed_count).contains(&n)))
+                .map(|(i, _)| (i % width, i / width));
+            let (mut min, mut max) = ((width, width), (0, 0));
+            for (x, y) in changed {
+                min = (min.0.min(x), min.1.min(y));
+                max = (max.0.max(x), max.1.max(y));
+            }
+            let (left, top) = (pixel(min.0), pixel(min.1));
+            let (right, bottom) = (pixel(max.0 + 1), pixel(max.1 + 1));
+            let region = imageops::crop_imm(&image, left, top, right - left, bottom - top);
+            (left, top, region.to_image())
+        };
+        previous_newest = newest;
+
+        let delay = if placed_count == codewords_count {
+            options.final_delay_ms
+        } else {
+            options.frame_delay_ms
+        };
+        let frame = indexed_frame(&image, left.as_u16(), top.as_u16(), delay);
+        encoder.write_frame(&frame).map_err(encoding_error)?;
+    }
+    Ok(())
+}
+
+/// Converts an image into a frame with a palette of exactly its colours,
+/// which avoids quantization since the animation uses only a handful.
+fn indexed_frame(image: &RgbaImage, left: u16, top: u16, delay_ms: u32) -> Frame<'static> {
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let buffer = image
+        .pixels()
+        .map(|Rgba([r, g, b, _])| {
+            let color = [*r, *g, *b];
+            let index = palette.iter().position(|c| *c == color).unwrap_or_else(|| {
+                palette.push(color);
+                palette.len() - 1
+            });
+            u8::try_from(index).expect("the animation uses fewer than 256 colours")
+        })
+        .collect::<Vec<_>>();
+    Frame {
+        left,
+        top,
+        width: image.width().as_u16(),
+        height: image.height().as_u16(),
+        buffer: Cow::Owned(buffer),
+        palette: Some(palette.concat()),
+        delay: (delay_ms / 10).try_into().unwrap_or(u16::MAX),
+        dispose: DisposalMethod::Keep,
+        ..Frame::default()
+    }
+}
+
+fn encoding_error(err: EncodingError) -> ImageError {
+    match err {
+        EncodingError::Io(err) => ImageError::IoError(err),
+        err @ EncodingError::Format(_) => ImageError::Encoding(ImageEncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Gif),
+            err,
+        )),
+    }
+}
+
+/// Returns the colour of a placed module of the `n`-th codeword.
+fn codeword_color(role: ModuleRole, n: usize, is_newest: bool, color: Color) -> Rgba<u8> {
+    let (dark, light) = if is_newest {
+        ([200, 0, 0], [255, 140, 140])
+    } else {
+        match (role, n % 2) {
+            (ModuleRole::Codeword { kind: CodewordKind::Data, .. }, 0) => {
+                ([20, 60, 160], [150, 180, 240])
+            }
+            (ModuleRole::Codeword { kind: CodewordKind::Data, .. }, _) => {
+                ([40, 110, 200], [190, 215, 250])
+            }
+            (_, 0) => ([190, 90, 0], [250, 190, 120]),
+            (_, _) => ([220, 140, 0], [255, 220, 160]),
+        }
+    };
+    let [r, g, b] = color.select(dark, light);
+    Rgba([r, g, b, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+
+    use crate::pipeline::EncodedBits;
+    use crate::types::EcLevel;
+
+    #[test]
+    fn test_frames_and_delays() {
+        // Version 1-M has 26 codewords, so 4 per frame take 7 frames.
+        let placed = EncodedBits::new(b"01234567", EcLevel::M)
+            .and_then(EncodedBits::into_codewords)
+            .unwrap()
+            .place();
+        let options = GifOptions {
+            codewords_per_frame: Some(4),
+            module_size: 2,
+            ..GifOptions::default()
+        };
+        let mut output = Vec::new();
+        write_placement_gif(&placed, &mut output, &options).unwrap();
+
+        let frames = GifDecoder::new(Cursor::new(output))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        let delays = frames
+            .iter()
+            .map(|frame| {
+                let (numerator, denominator) = frame.delay().numer_denom_ms();
+                numerator / denominator
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [80, 80, 80, 80, 80, 80, 2000]);
+
+        // The partial frames add up to a symbol with every module placed.
+        let last = frames.last().unwrap().buffer();
+        assert_eq!(last.dimensions(), (58, 58));
+        assert!(last.pixels().all(|pixel| *pixel != Rgba([200, 200, 200, 255])));
+        let first = frames[0].buffer();
+        assert!(first.pixels().any(|pixel| *pixel == Rgba([200, 200, 200, 255])));
+    }
+}
@@ -44,6 +44,10 @@ impl<P: image::Pixel + 'static> Canvas for (P, ImageBuffer<P, Vec<P::Subpixel>>)
         self.1.put_pixel(x, y, self.0);
     }
 
+    fn draw_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        self.1.put_pixel(x, y, pixel);
+    }
+
     fn into_image(self) -> ImageBuffer<P, Vec<P::Subpixel>> {
         self.1
     }
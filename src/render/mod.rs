@@ -3,6 +3,7 @@ use crate::cast::As;
 use crate::types::Color;
 
 // pub mod string;
+pub mod gif;
 pub mod image;
 
 pub trait Canvas: Sized {
@@ -15,6 +16,9 @@ pub trait Canvas: Sized {
     /// Draws a single dark pixel at the (x, y) coordinate.
     fn draw_dark_pixel(&mut self, x: u32, y: u32);
 
+    /// Draws a single pixel of an arbitrary color at the (x, y) coordinate.
+    fn draw_pixel(&mut self, x: u32, y: u32, pixel: Self::Pixel);
+
     fn draw_dark_rect(&mut self, left: u32, top: u32, width: u32, height: u32) {
         for y in top..(top + height) {
             for x in left..(left + width) {
@@ -69,6 +73,12 @@ impl<'a, P: Pixel> Renderer<'a, P>{
         }
     }
 
+    /// Sets the size of a module in pixels.
+    pub fn module_dimensions(&mut self, width: u32, height: u32) -> &mut Self {
+        self.module_size = (width.max(1), height.max(1));
+        self
+    }
+
     pub fn build(&self) -> P::Image {
         let w = self.modules_count;
         let qz = if self.has_quiet_zone {
@@ -97,4 +107,36 @@ impl<'a, P: Pixel> Renderer<'a, P>{
 
         canvas.into_image()
     }
+
+    /// Like `build`, but asks `color_of` for the color of every module,
+    /// including light ones. It receives the module index in left-to-right,
+    /// then top-to-bottom order and the module color. The quiet zone uses the
+    /// light color.
+    pub fn build_with<F>(&self, color_of: F) -> P::Image
+    where
+        F: Fn(usize, Color) -> P,
+    {
+        let w = self.modules_count;
+        let qz = if self.has_quiet_zone {
+            self.quiet_zone
+        } else {
+            0
+        };
+        let width = w + 2 * qz;
+
+        let (mw, mh) = self.module_size;
+        let mut canvas = P::Canvas::new(width * mw, width * mh, self.dark_color, self.light_color);
+        for (i, color) in self.content.iter().enumerate() {
+            let x = i.as_u32() % w + qz;
+            let y = i.as_u32() / w + qz;
+            let pixel = color_of(i, *color);
+            for py in y * mh..(y + 1) * mh {
+                for px in x * mw..(x + 1) * mw {
+                    canvas.draw_pixel(px, py, pixel);
+                }
+            }
+        }
+
+        canvas.into_image()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,194 @@
+//! A self-contained HTML page explaining how a symbol was built.
+//!
+//! The page shows the symbol as an SVG grid. Hovering a module shows its role,
+//! the codeword and bit it carries and its value before masking. Side panels
+//! list the segments, the annotated bit stream, the error correction blocks
+//! and the penalty score of every mask. All data is embedded as inline JSON,
+//! so the page has no external dependencies.
+
+use std::fmt::Write;
+
+use serde_json::json;
+
+use crate::bits;
+use crate::canvas::{Module, ALL_PATTERNS_QR};
+use crate::mask::IsoPenalty;
+use crate::pipeline::EncodedBits;
+use crate::provenance::ProvenanceMap;
+use crate::types::{Color, EcLevel, QrResult, Version};
+
+/// The size of a module in the SVG, in pixels.
+const MODULE_SIZE: i16 = 12;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 1em; display: flex; gap: 2em; align-items: flex-start; }
+#symbol rect.m:hover { stroke: #e33; stroke-width: 2; }
+#info { font-family: monospace; min-height: 5em; white-space: pre; }
+section { margin-bottom: 1.5em; }
+table { border-collapse: collapse; font-family: monospace; font-size: 12px; }
+td, th { border: 1px solid #ccc; padding: 1px 6px; text-align: left; }
+tr.selected { background: #dfd; }
+";
+
+const SCRIPT: &str = r#"
+const qr = JSON.parse(document.getElementById('qr-data').textContent);
+const info = document.getElementById('info');
+document.querySelectorAll('#symbol rect.m').forEach(rect => {
+  rect.addEventListener('mouseenter', () => {
+    const i = Number(rect.dataset.i);
+    const m = qr.modules[i];
+    const x = i % qr.width, y = Math.floor(i / qr.width);
+    info.textContent = `(${x}, ${y}) ${m.dark ? 'dark' : 'light'}\n${m.role}\n` +
+      (m.unmasked === null ? 'not masked' : `before masking: ${m.unmasked ? 'dark' : 'light'}`);
+  });
+});
+function table(id, headers, rows) {
+  const t = document.getElementById(id);
+  t.innerHTML = '<tr>' + headers.map(h => `<th>${h}</th>`).join('') + '</tr>';
+  rows.forEach(r => {
+    const tr = t.insertRow();
+    if (r.selected) tr.className = 'selected';
+    r.cells.forEach(c => { tr.insertCell().textContent = c; });
+  });
+}
+const hex = bytes => bytes.map(b => b.toString(16).padStart(2, '0')).join(' ');
+table('segments', ['mode', 'bytes', 'text'],
+  qr.segments.map(s => ({ cells: [s.mode, `${s.begin}..${s.end}`, s.text] })));
+table('bits', ['bits', 'value', 'meaning'],
+  qr.bits.map(b => ({ cells: [`${b.start}..${b.end}`, b.bits, b.meaning] })));
+table('blocks', ['block', 'data', 'ec'],
+  qr.blocks.map((b, i) => ({ cells: [i, hex(b.data), hex(b.ec)] })));
+table('masks', ['pattern', 'penalty'],
+  qr.masks.map(m => ({ cells: [m.pattern, m.penalty], selected: m.selected })));
+"#;
+
+/// Builds the HTML report of the symbol encoding `data`.
+pub fn html_report(data: &[u8], ec_level: EcLevel) -> QrResult<String> {
+    let (_, segments) = bits::optimal_segments(data, ec_level)?;
+    let encoded = EncodedBits::new(data, ec_level)?;
+    let bits_json = encoded.bits().annotation_json_value();
+    let codewords = encoded.into_codewords()?;
+    let version = codewords.version();
+    let Version::Normal(version_number) = version;
+    let map = ProvenanceMap::new(version, ec_level)?;
+    let blocks = codewords
+        .data_blocks()
+        .iter()
+        .zip(codewords.ec_blocks())
+        .map(|(data, ec)| json!({ "data": data, "ec": ec }))
+        .collect::<Vec<_>>();
+
+    let placed = codewords.place();
+    let masks = ALL_PATTERNS_QR
+        .iter()
+        .map(|pattern| {
+            let mut canvas = placed.canvas().clone();
+            canvas.apply_mask(*pattern);
+            (*pattern, canvas.compute_total_penalty_scores())
+        })
+        .collect::<Vec<_>>();
+    let symbol = placed.clone().mask(&IsoPenalty);
+    let selected = symbol.mask_pattern();
+
+    let width = version.width();
+    let mut modules = Vec::new();
+    let mut svg = String::new();
+    let side = width * MODULE_SIZE;
+    let _ = write!(
+        svg,
+        r#"<svg id="symbol" xmlns="http://www.w3.org/2000/svg" width="{side}" height="{side}" viewBox="0 0 {side} {side}" shape-rendering="crispEdges">"#
+    );
+    for y in 0..width {
+        for x in 0..width {
+            let color = Color::from(symbol.canvas().get(x, y));
+            let unmasked = match placed.canvas().get(x, y) {
+                Module::Unmasked(c) => Some(c == Color::Dark),
+                Module::Empty => Some(false),
+                Module::Masked(_) => None,
+            };
+            modules.push(json!({
+                "role": map.get(x, y).to_string(),
+                "dark": color == Color::Dark,
+                "unmasked": unmasked,
+            }));
+            let _ = write!(
+                svg,
+                r#"<rect class="m" data-i="{}" x="{}" y="{}" width="{MODULE_SIZE}" height="{MODULE_SIZE}" fill="{}"/>"#,
+                y * width + x,
+                x * MODULE_SIZE,
+                y * MODULE_SIZE,
+                color.select("#000", "#fff"),
+            );
+        }
+    }
+    svg.push_str("</svg>");
+
+    let report = json!({
+        "data": String::from_utf8_lossy(data),
+        "version": version_number,
+        "ec_level": format!("{ec_level:?}"),
+        "width": width,
+        "modules": modules,
+        "segments": segments
+            .iter()
+            .map(|s| json!({
+                "mode": format!("{:?}", s.mode),
+                "begin": s.begin,
+                "end": s.end,
+                "text": String::from_utf8_lossy(&data[s.begin..s.end]),
+            }))
+            .collect::<Vec<_>>(),
+        "bits": bits_json,
+        "blocks": blocks,
+        "masks": masks
+            .iter()
+            .map(|(pattern, penalty)| json!({
+                "pattern": *pattern as u8,
+                "penalty": penalty,
+                "selected": *pattern == selected,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    // Keep the JSON from closing the script element early.
+    let report = report.to_string().replace("</", "<\\/");
+
+    let title = escape_html(&String::from_utf8_lossy(data));
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>QR code: {title}</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<div>
+<h2>Version {}-{ec_level:?}, mask {}</h2>
+{svg}
+<div id="info">Hover a module to see its role.</div>
+</div>
+<div>
+<section><h3>Segments</h3><table id="segments"></table></section>
+<section><h3>Bit stream</h3><table id="bits"></table></section>
+<section><h3>Blocks</h3><table id="blocks"></table></section>
+<section><h3>Masks</h3><table id="masks"></table></section>
+</div>
+<script type="application/json" id="qr-data">{report}</script>
+<script>{SCRIPT}</script>
+</body>
+</html>
+"#,
+        version_number,
+        selected as u8,
+    );
+    Ok(html)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,279 @@
+//! Turning grayscale photos into black and white bit matrices.
+//!
+//! Every method decides on a threshold for each pixel; pixels at or below
+//! their threshold become dark. `Otsu` uses a single threshold for the whole
+//! image, while `HybridBlockMean` and `Sauvola` adapt it to the neighbourhood
+//! of each pixel, which copes much better with uneven lighting.
+
+use image::GrayImage;
+
+use crate::cast::{As, Truncate};
+use crate::scan::BitMatrix;
+
+/// Converts a grayscale image into a bit matrix.
+pub trait Binarizer {
+    fn binarize(&self, image: &GrayImage) -> BitMatrix;
+}
+
+/// A global threshold maximizing the variance between the dark and light
+/// classes of the histogram.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Otsu;
+
+/// Returns the threshold chosen by Otsu's method.
+pub fn otsu_threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0_u64; 256];
+    for pixel in image.pixels() {
+        histogram[usize::from(pixel.0[0])] += 1;
+    }
+    let total = histogram.iter().sum::<u64>();
+    let total_sum = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, count)| u64::from(value.as_u32()) * count)
+        .sum::<u64>();
+
+    let (mut best, mut best_variance) = (0, 0.0);
+    let (mut dark_count, mut dark_sum) = (0_u64, 0_u64);
+    for (value, count) in histogram.iter().enumerate() {
+        dark_count += count;
+        dark_sum += u64::from(value.as_u32()) * count;
+        let light_count = total - dark_count;
+        if dark_count == 0 || light_count == 0 {
+            continue;
+        }
+        let dark_mean = dark_sum as f64 / dark_count as f64;
+        let light_mean = (total_sum - dark_sum) as f64 / light_count as f64;
+        let variance =
+            dark_count as f64 * light_count as f64 * (dark_mean - light_mean).powi(2);
+        if variance > best_variance {
+            best = value;
+            best_variance = variance;
+        }
+    }
+    best.as_u16().truncate_as_u8()
+}
+
+impl Binarizer for Otsu {
+    fn binarize(&self, image: &GrayImage) -> BitMatrix {
+        let threshold = otsu_threshold(image);
+        threshold_each(image, |_, _| threshold)
+    }
+}
+
+/// A local threshold computed from 8 × 8 blocks, after ZXing's
+/// `HybridBinarizer`.
+///
+/// Each block gets a black point: its mean, or for nearly uniform blocks a
+/// value below its minimum (borrowed from its neighbours when possible) so
+/// that they become light. Each pixel is then compared against the average
+/// black point of the 5 × 5 blocks around its own. Images smaller than 5 × 5
+/// blocks fall back to `Otsu`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HybridBlockMean;
+
+/// The size of a block of `HybridBlockMean`, in pixels.
+const BLOCK_SIZE: u32 = 8;
+
+/// Blocks whose contrast is at most this are considered uniform.
+const MIN_DYNAMIC_RANGE: u32 = 24;
+
+impl Binarizer for HybridBlockMean {
+    fn binarize(&self, image: &GrayImage) -> BitMatrix {
+        let (width, height) = image.dimensions();
+        let (blocks_x, blocks_y) = (width.div_ceil(BLOCK_SIZE), height.div_ceil(BLOCK_SIZE));
+        if blocks_x < 5 || blocks_y < 5 {
+            return Otsu.binarize(image);
+        }
+
+        // The block grid covers the whole image; when the size is not a
+        // multiple of the block size, the last row and column of blocks are
+        // clamped to end at the border and overlap their neighbours.
+        let block_origin = |b: u32, size: u32| (b * BLOCK_SIZE).min(size - BLOCK_SIZE);
+        let mut black_points = vec![0_u32; (blocks_x * blocks_y).as_usize()];
+        for by in 0..blocks_y {
+            for bx in 0..blocks_x {
+                let left = block_origin(bx, width);
+                let top = block_origin(by, height);
+                let (mut sum, mut min, mut max) = (0, u32::MAX, 0);
+                for y in top..top + BLOCK_SIZE {
+                    for x in left..left + BLOCK_SIZE {
+                        let value = u32::from(image.get_pixel(x, y).0[0]);
+                        sum += value;
+                        min = min.min(value);
+                        max = max.max(value);
+                    }
+                }
+                let mut average = sum / (BLOCK_SIZE * BLOCK_SIZE);
+                if max - min <= MIN_DYNAMIC_RANGE {
+                    // A uniform block is assumed to be light, unless its
+                    // neighbours say otherwise.
+                    average = min / 2;
+                    if bx > 0 && by > 0 {
+                        let at = |x: u32, y: u32| black_points[(y * blocks_x + x).as_usize()];
+                        let neighbours =
+                            (at(bx, by - 1) + 2 * at(bx - 1, by) + at(bx - 1, by - 1)) / 4;
+                        if min < neighbours {
+                            average = neighbours;
+                        }
+                    }
+                }
+                black_points[(by * blocks_x + bx).as_usize()] = average;
+            }
+        }
+
+        threshold_each(image, |x, y| {
+            let bx = (x / BLOCK_SIZE).clamp(2, blocks_x - 3);
+            let by = (y / BLOCK_SIZE).clamp(2, blocks_y - 3);
+            let mut sum = 0;
+            for ny in by - 2..=by + 2 {
+                for nx in bx - 2..=bx + 2 {
+                    sum += black_points[(ny * blocks_x + nx).as_usize()];
+                }
+            }
+            (sum / 25).as_u16().truncate_as_u8()
+        })
+    }
+}
+
+/// Sauvola's local threshold `m × (1 + k × (s / 128 − 1))`, where `m` and `s`
+/// are the mean and standard deviation of the square window around each
+/// pixel.
+#[derive(Debug, Copy, Clone)]
+pub struct Sauvola {
+    /// The window spans `2 × radius + 1` pixels in each direction. It should
+    /// cover a few modules.
+    pub radius: u32,
+
+    /// How much the standard deviation lowers the threshold, usually between
+    /// 0.2 and 0.5.
+    pub k: f64,
+}
+
+impl Default for Sauvola {
+    fn default() -> Self {
+        Self { radius: 12, k: 0.34 }
+    }
+}
+
+impl Binarizer for Sauvola {
+    fn binarize(&self, image: &GrayImage) -> BitMatrix {
+        let (width, height) = image.dimensions();
+        let stride = width.as_usize() + 1;
+
+        // Integral images of the values and their squares, with an extra row
+        // and column of zeros.
+        let mut sums = vec![0_u64; stride * (height.as_usize() + 1)];
+        let mut squares = sums.clone();
+        for y in 0..height.as_usize() {
+            for x in 0..width.as_usize() {
+                let value = u64::from(image.get_pixel(x.as_u32(), y.as_u32()).0[0]);
+                let i = (y + 1) * stride + x + 1;
+                sums[i] = value + sums[i - 1] + sums[i - stride] - sums[i - stride - 1];
+                squares[i] =
+                    value * value + squares[i - 1] + squares[i - stride] - squares[i - stride - 1];
+            }
+        }
+
+        threshold_each(image, |x, y| {
+            let left = x.saturating_sub(self.radius).as_usize();
+            let top = y.saturating_sub(self.radius).as_usize();
+            let right = (x + self.radius + 1).min(width).as_usize();
+            let bottom = (y + self.radius + 1).min(height).as_usize();
+            let area = |table: &[u64]| {
+                table[bottom * stride + right] + table[top * stride + left]
+                    - table[top * stride + right]
+                    - table[bottom * stride + left]
+            };
+            let count = ((right - left) * (bottom - top)) as f64;
+            let mean = area(&sums) as f64 / count;
+            let variance = (area(&squares) as f64 / count - mean * mean).max(0.0);
+            let threshold = mean * (1.0 + self.k * (variance.sqrt() / 128.0 - 1.0));
+            threshold.clamp(0.0, 255.0) as u8
+        })
+    }
+}
+
+/// Marks every pixel at or below its threshold as dark.
+fn threshold_each<F: Fn(u32, u32) -> u8>(image: &GrayImage, threshold: F) -> BitMatrix {
+    let mut matrix = BitMatrix::new(image.width(), image.height());
+    for (x, y, pixel) in image.enumerate_pixels() {
+        matrix.set(x, y, pixel.0[0] <= threshold(x, y));
+    }
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    use crate::qr_code::QrCode;
+    use crate::types::Color;
+
+    #[test]
+    fn test_otsu_threshold() {
+        // Two clusters, 50 ± 10 and 180 ± 10, in a 3 : 2 ratio.
+        let image = GrayImage::from_fn(100, 50, |x, y| {
+            let spread = (x * 7 + y * 3) % 21;
+            Luma([if x < 60 { 40 + spread } else { 170 + spread }.as_u16().truncate_as_u8()])
+        });
+        let threshold = otsu_threshold(&image);
+        assert!((60..170).contains(&threshold), "{threshold}");
+        let matrix = Otsu.binarize(&image);
+        assert!(matrix.is_dark(59, 10) && !matrix.is_dark(60, 10));
+    }
+
+    /// A render of `code` with 3 pixels per module, lit so unevenly that
+    /// light modules in the top left are darker than dark modules in the
+    /// bottom right, and cropped by `crop` pixels on the right and bottom.
+    fn unevenly_lit(code: &QrCode, crop: u32) -> GrayImage {
+        let image = code.render::<Luma<u8>>().module_dimensions(3, 3).build();
+        let (width, height) = (image.width() - crop, image.height() - crop);
+        GrayImage::from_fn(width, height, |x, y| {
+            let t = f64::from(x + y) / f64::from(width + height);
+            let dark = image.get_pixel(x, y).0[0] < 128;
+            let value = if dark { 10.0 + 120.0 * t } else { 80.0 + 170.0 * t };
+            Luma([value.round() as u8])
+        })
+    }
+
+    /// The modules of `code` which `matrix`, binarized from a render with 3
+    /// pixels per module, gets wrong at their centres.
+    fn wrong_modules(code: &QrCode, matrix: &BitMatrix) -> usize {
+        let width = code.width();
+        code.to_colors()
+            .iter()
+            .enumerate()
+            .filter(|(i, color)| {
+                let (x, y) = ((4 + i % width).as_u32() * 3 + 1, (4 + i / width).as_u32() * 3 + 1);
+                matrix.is_dark(x, y) != (**color == Color::Dark)
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_uneven_lighting() {
+        let code = QrCode::new(b"uneven lighting needs a local threshold").unwrap();
+        let image = unevenly_lit(&code, 0);
+        assert!(wrong_modules(&code, &Otsu.binarize(&image)) > 0);
+        assert_eq!(wrong_modules(&code, &HybridBlockMean.binarize(&image)), 0);
+        assert_eq!(wrong_modules(&code, &Sauvola::default().binarize(&image)), 0);
+    }
+
+    #[test]
+    fn test_partial_edge_blocks() {
+        // The symbol spans 29 × 3 = 87 pixels with its quiet zone; cropping
+        // 0 to 7 pixels covers every remainder modulo the block size.
+        let code = QrCode::new(b"edge").unwrap();
+        for crop in 0..BLOCK_SIZE {
+            let image = unevenly_lit(&code, crop);
+            let matrix = HybridBlockMean.binarize(&image);
+            assert_eq!(wrong_modules(&code, &matrix), 0, "{crop}");
+            // The quiet zone along the edges stays light.
+            let (width, height) = image.dimensions();
+            assert!((0..height).all(|y| !matrix.is_dark(width - 1, y)));
+            assert!((0..width).all(|x| !matrix.is_dark(x, height - 1)));
+        }
+    }
+}
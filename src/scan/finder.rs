@@ -0,0 +1,415 @@
+//! Locating finder patterns in a binarized image.
+//!
+//! Every row is scanned for runs of dark, light, dark, light and dark pixels
+//! in the ratio 1:1:3:1:1, the same shape `compute_finder_penalty_score`
+//! looks for inside a symbol. Each hit is cross-checked vertically, then
+//! horizontally again and diagonally through the refined centre, and hits
+//! close to each other are merged into a single pattern. Finally, triples of
+//! patterns forming a plausible right-angled corner are selected.
+
+use crate::cast::As;
+use crate::scan::{quad_contains, BitMatrix, Point};
+use crate::types::Version;
+
+/// The relative deviation from the 1:1:3:1:1 ratio accepted along rows and
+/// columns.
+const TOLERANCE: f64 = 0.5;
+
+/// The relative deviation accepted along the diagonal, which suffers more
+/// from blur and rounding.
+const DIAGONAL_TOLERANCE: f64 = 0.75;
+
+/// The largest accepted ratio between the module sizes of the patterns of a
+/// triple, and between the lengths of its two sides.
+const MAX_SIZE_RATIO: f64 = 1.6;
+
+/// The largest accepted cosine of the corner angle of a triple, i.e. about
+/// 90° ± 23°.
+const MAX_CORNER_COSINE: f64 = 0.4;
+
+/// A finder pattern found in an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FinderPattern {
+    pub center: Point,
+
+    /// The estimated size of a module, in pixels.
+    pub module_size: f64,
+
+    /// The number of rows in which the pattern was detected. Patterns seen
+    /// only once are likely noise.
+    pub count: usize,
+}
+
+/// Three finder patterns which can be the corners of one symbol.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FinderTriple {
+    pub top_left: FinderPattern,
+    pub top_right: FinderPattern,
+    pub bottom_left: FinderPattern,
+
+    /// The module size measured along the sides of the symbol, which unlike
+    /// the module sizes of the patterns does not depend on the rotation.
+    pub module_size: f64,
+
+    /// How far the triple is from a perfect square corner; 0 is perfect.
+    pub skew: f64,
+}
+
+impl FinderTriple {
+    /// Guesses the version from the distance between the pattern centres,
+    /// which lie 7 modules less than the width apart.
+    pub fn estimated_version(&self) -> Version {
+        let module_size = self.module_size;
+        let top = self.top_left.center.distance(self.top_right.center) / module_size;
+        let left = self.top_left.center.distance(self.bottom_left.center) / module_size;
+        let width = (top + left) / 2.0 + 7.0;
+        Version::Normal(((width - 17.0) / 4.0).round().clamp(1.0, 40.0) as i16)
+    }
+
+    pub const fn patterns(&self) -> [FinderPattern; 3] {
+        [self.top_left, self.top_right, self.bottom_left]
+    }
+
+    /// Whether `point` lies inside the parallelogram spanned by the pattern
+    /// centres. A triple enclosing a pattern of another symbol was most likely
+    /// made of patterns from several symbols.
+    pub fn encloses(&self, point: Point) -> bool {
+        let (tl, tr, bl) = (self.top_left.center, self.top_right.center, self.bottom_left.center);
+        let br = Point::new(tr.x + bl.x - tl.x, tr.y + bl.y - tl.y);
+        quad_contains(&[tl, tr, br, bl], point)
+    }
+
+    /// Orders three patterns as a triple, or returns `None` if they cannot be
+    /// the corners of one symbol.
+    fn from_patterns(matrix: &BitMatrix, patterns: [FinderPattern; 3]) -> Option<Self> {
+        let sizes = patterns.map(|p| p.module_size);
+        let min_size = sizes.iter().copied().fold(f64::MAX, f64::min);
+        let max_size = sizes.iter().copied().fold(0.0, f64::max);
+        if max_size > min_size * MAX_SIZE_RATIO {
+            return None;
+        }
+
+        // The top-left pattern is opposite the longest side.
+        let corner = (0..3)
+            .max_by(|a, b| {
+                let side = |i: usize| {
+                    patterns[(i + 1) % 3].center.distance(patterns[(i + 2) % 3].center)
+                };
+                side(*a).total_cmp(&side(*b))
+            })
+            .expect("three patterns");
+        let top_left = patterns[corner];
+        let mut top_right = patterns[(corner + 1) % 3];
+        let mut bottom_left = patterns[(corner + 2) % 3];
+
+        let (ax, ay) =
+            (top_right.center.x - top_left.center.x, top_right.center.y - top_left.center.y);
+        let (bx, by) =
+            (bottom_left.center.x - top_left.center.x, bottom_left.center.y - top_left.center.y);
+        let (a, b) = (ax.hypot(ay), bx.hypot(by));
+        let module_size = sizes.iter().sum::<f64>() / 3.0;
+        let width_range = 10.0..200.0;
+        if a.max(b) > a.min(b) * MAX_SIZE_RATIO
+            || !width_range.contains(&(a / module_size))
+            || !width_range.contains(&(b / module_size))
+        {
+            return None;
+        }
+        let cosine = (ax * bx + ay * by) / (a * b);
+        if cosine.abs() > MAX_CORNER_COSINE {
+            return None;
+        }
+
+        // With y pointing down, going from the top-right to the bottom-left
+        // pattern turns clockwise around the top-left one.
+        if ax * by - ay * bx < 0.0 {
+            std::mem::swap(&mut top_right, &mut bottom_left);
+        }
+        let widths = [
+            measure_pattern(matrix, top_left, top_right.center),
+            measure_pattern(matrix, top_left, bottom_left.center),
+            measure_pattern(matrix, top_right, top_left.center),
+            measure_pattern(matrix, bottom_left, top_left.center),
+        ];
+        let module_size = match widths.iter().flatten().count() {
+            0 => module_size,
+            n => widths.iter().flatten().sum::<f64>() / (7.0 * n as f64),
+        };
+        Some(Self {
+            top_left,
+            top_right,
+            bottom_left,
+            module_size,
+            skew: cosine.abs() + (a - b).abs() / a.max(b),
+        })
+    }
+}
+
+/// Measures the width of a finder pattern in pixels along the line from its
+/// centre towards `toward`, by walking across the dark core, the light ring
+/// and the dark ring in both directions.
+fn measure_pattern(matrix: &BitMatrix, pattern: FinderPattern, toward: Point) -> Option<f64> {
+    let center = pattern.center;
+    let length = center.distance(toward);
+    let (ux, uy) = ((toward.x - center.x) / length, (toward.y - center.y) / length);
+    let max_distance = pattern.module_size * 7.0;
+    let half = |sign: f64| {
+        let mut rings = 0;
+        let mut t = 0.0;
+        while t < max_distance {
+            let (x, y) = (center.x + sign * t * ux, center.y + sign * t * uy);
+            let dark = matrix.get(x.floor() as i64, y.floor() as i64)?;
+            if dark != (rings % 2 == 0) {
+                rings += 1;
+                if rings == 3 {
+                    // The edge lies somewhere within the last step.
+                    return Some(t - 0.25);
+                }
+            }
+            t += 0.5;
+        }
+        None
+    };
+    Some(half(1.0)? + half(-1.0)?)
+}
+
+/// The lengths of five consecutive runs of alternating colours, starting and
+/// ending with dark.
+pub(super) type Runs = [u32; 5];
+
+fn is_finder_ratio(runs: &Runs, tolerance: f64) -> bool {
+    let total = runs.iter().sum::<u32>();
+    if total < 7 || runs.contains(&0) {
+        return false;
+    }
+    let module_size = f64::from(total) / 7.0;
+    runs.iter().zip([1.0, 1.0, 3.0, 1.0, 1.0]).all(|(run, weight)| {
+        (f64::from(*run) - module_size * weight).abs() < module_size * tolerance * weight
+    })
+}
+
+/// Measures the runs along the line through pixel (x, y) in direction
+/// (dx, dy). The pixel must be in the centre run. Outer runs longer than
+/// `max_run` are cut short.
+///
+/// Returns the offset of the centre of the middle run from the centre of the
+/// pixel, in steps along the line, together with the runs.
+pub(super) fn cross_check(
+    matrix: &BitMatrix,
+    x: i64,
+    y: i64,
+    (dx, dy): (i64, i64),
+    max_run: u32,
+) -> Option<(f64, Runs)> {
+    let at = |t: i64| matrix.get(x + t * dx, y + t * dy);
+    let mut runs = [0; 5];
+
+    let mut t = 0;
+    while at(t) == Some(true) {
+        runs[2] += 1;
+        t -= 1;
+    }
+    while at(t) == Some(false) && runs[1] <= max_run {
+        runs[1] += 1;
+        t -= 1;
+    }
+    while at(t) == Some(true) && runs[0] <= max_run {
+        runs[0] += 1;
+        t -= 1;
+    }
+
+    let before = runs[2];
+    let mut t = 1;
+    while at(t) == Some(true) {
+        runs[2] += 1;
+        t += 1;
+    }
+    let after = runs[2] - before;
+    while at(t) == Some(false) && runs[3] <= max_run {
+        runs[3] += 1;
+        t += 1;
+    }
+    while at(t) == Some(true) && runs[4] <= max_run {
+        runs[4] += 1;
+        t += 1;
+    }
+
+    if before == 0 {
+        return None;
+    }
+    // The middle run covers the offsets 1 - before ..= after.
+    Some(((f64::from(after) - f64::from(before) + 1.0) / 2.0, runs))
+}
+
+/// Confirms a candidate found in row `y`, centred at `x` with the given runs,
+/// and returns its refined centre and module size.
+fn confirm(matrix: &BitMatrix, x: f64, y: u32, row_runs: &Runs) -> Option<(Point, f64)> {
+    let row_total = row_runs.iter().sum::<u32>();
+    let max_run = row_runs[2];
+
+    let column = x.floor() as i64;
+    let (offset, vertical) = cross_check(matrix, column, i64::from(y), (0, 1), max_run)?;
+    let vertical_total = vertical.iter().sum::<u32>();
+    if !is_finder_ratio(&vertical, TOLERANCE)
+        || 5 * row_total.abs_diff(vertical_total) >= 2 * row_total
+    {
+        return None;
+    }
+    let center_y = f64::from(y) + 0.5 + offset;
+
+    let row = center_y.floor() as i64;
+    let (offset, horizontal) = cross_check(matrix, column, row, (1, 0), max_run)?;
+    if !is_finder_ratio(&horizontal, TOLERANCE) {
+        return None;
+    }
+    let center_x = column as f64 + 0.5 + offset;
+
+    let (_, diagonal) = cross_check(matrix, center_x.floor() as i64, row, (1, 1), max_run)?;
+    if !is_finder_ratio(&diagonal, DIAGONAL_TOLERANCE) {
+        return None;
+    }
+
+    let module_size = f64::from(horizontal.iter().sum::<u32>() + vertical_total) / 14.0;
+    Some((Point::new(center_x, center_y), module_size))
+}
+
+/// Adds a confirmed detection, merging it into an existing pattern with
+/// about the same centre and module size.
+fn merge(patterns: &mut Vec<FinderPattern>, center: Point, module_size: f64) {
+    let existing = patterns.iter_mut().find(|p| {
+        (p.center.x - center.x).abs() <= p.module_size
+            && (p.center.y - center.y).abs() <= p.module_size
+            && (p.module_size - module_size).abs() <= p.module_size.max(1.0)
+    });
+    match existing {
+        Some(p) => {
+            let count = p.count.as_u32();
+            let weight = |old: f64, new: f64| (old * f64::from(count) + new) / f64::from(count + 1);
+            p.center = Point::new(weight(p.center.x, center.x), weight(p.center.y, center.y));
+            p.module_size = weight(p.module_size, module_size);
+            p.count += 1;
+        }
+        None => patterns.push(FinderPattern {
+            center,
+            module_size,
+            count: 1,
+        }),
+    }
+}
+
+/// Finds all finder patterns in the matrix.
+pub fn find_finder_patterns(matrix: &BitMatrix) -> Vec<FinderPattern> {
+    let mut patterns = Vec::new();
+    for y in 0..matrix.height() {
+        let mut runs: Runs = [0; 5];
+        let mut state = 0;
+        // One extra light pixel past the end closes a pattern touching the
+        // right edge.
+        for x in 0..=matrix.width() {
+            let dark = x < matrix.width() && matrix.is_dark(x, y);
+            if dark {
+                if state % 2 == 1 {
+                    state += 1;
+                }
+                runs[state] += 1;
+            } else if state % 2 == 1 {
+                runs[state] += 1;
+            } else if state == 4 {
+                if is_finder_ratio(&runs, TOLERANCE) {
+                    let center =
+                        f64::from(x) - f64::from(runs[4] + runs[3]) - f64::from(runs[2]) / 2.0;
+                    if let Some((center, module_size)) = confirm(matrix, center, y, &runs) {
+                        merge(&mut patterns, center, module_size);
+                    }
+                }
+                // Keep the last three runs: they may start another pattern.
+                runs = [runs[2], runs[3], runs[4], 1, 0];
+                state = 3;
+            } else if runs[state] > 0 {
+                state += 1;
+                runs[state] += 1;
+            }
+        }
+    }
+    patterns
+}
+
+/// Selects the triples of patterns which can be the corners of a symbol,
+/// best first. A pattern may appear in several triples.
+pub fn find_triples(matrix: &BitMatrix, patterns: &[FinderPattern]) -> Vec<FinderTriple> {
+    // Prefer patterns confirmed in several rows, if there are enough of them.
+    let min_count = if patterns.iter().filter(|p| p.count >= 2).count() >= 3 { 2 } else { 1 };
+    let candidates = patterns.iter().filter(|p| p.count >= min_count).collect::<Vec<_>>();
+
+    let mut triples = Vec::new();
+    for (i, a) in candidates.iter().enumerate() {
+        for (j, b) in candidates.iter().enumerate().skip(i + 1) {
+            for c in candidates.iter().skip(j + 1) {
+                triples.extend(FinderTriple::from_patterns(matrix, [**a, **b, **c]));
+            }
+        }
+    }
+    triples.sort_by(|a, b| a.skew.total_cmp(&b.skew));
+    triples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    use crate::bits::Bits;
+    use crate::qr_code::QrCode;
+    use crate::scan::binarize::{Binarizer, Otsu};
+    use crate::types::EcLevel;
+
+    /// The binarized render of a symbol of `version` with `module_size`
+    /// pixels per module, and the width of the symbol in modules.
+    fn rendered(version: i16, module_size: u32) -> (BitMatrix, f64) {
+        let mut bits = Bits::new(Version::Normal(version));
+        bits.push_byte_data(b"finder").unwrap();
+        bits.push_terminator(EcLevel::L).unwrap();
+        let code = QrCode::with_bits(bits, EcLevel::L).unwrap();
+        let image = code.render::<Luma<u8>>().module_dimensions(module_size, module_size).build();
+        (Otsu.binarize(&image), code.width() as f64)
+    }
+
+    fn assert_near(found: Point, (x, y): (f64, f64), tolerance: f64) {
+        assert!(found.distance(Point::new(x, y)) <= tolerance, "{found:?} != ({x}, {y})");
+    }
+
+    #[test]
+    fn test_rendered_symbols() {
+        for version in [1, 2, 7, 15, 25] {
+            let (matrix, width) = rendered(version, 4);
+            let patterns = find_finder_patterns(&matrix);
+            let triple = find_triples(&matrix, &patterns)[0];
+
+            // The quiet zone is 4 modules wide, and the centres are 3.5
+            // modules from the edges of the symbol.
+            let (near, far) = ((4.0 + 3.5) * 4.0, (4.0 + width - 3.5) * 4.0);
+            assert_near(triple.top_left.center, (near, near), 0.5);
+            assert_near(triple.top_right.center, (far, near), 0.5);
+            assert_near(triple.bottom_left.center, (near, far), 0.5);
+            assert!((triple.module_size - 4.0).abs() < 0.1);
+            assert!(triple.patterns().iter().all(|p| (p.module_size - 4.0).abs() < 0.5));
+            assert!(triple.skew < 0.01);
+            assert_eq!(triple.estimated_version(), Version::Normal(version));
+        }
+    }
+
+    #[test]
+    fn test_rotated_symbol() {
+        // Rotating by 90° clockwise moves the top left pattern to the top
+        // right, and so on.
+        let code = QrCode::new(b"rotated by a quarter turn").unwrap();
+        let image = code.render::<Luma<u8>>().module_dimensions(3, 3).build();
+        let matrix = Otsu.binarize(&image::imageops::rotate90(&image));
+        let triple = find_triples(&matrix, &find_finder_patterns(&matrix))[0];
+        let (near, far) = ((4.0 + 3.5) * 3.0, (4.0 + code.width() as f64 - 3.5) * 3.0);
+        assert_near(triple.top_left.center, (far, near), 0.5);
+        assert_near(triple.top_right.center, (far, far), 0.5);
+        assert_near(triple.bottom_left.center, (near, near), 0.5);
+        assert_eq!(triple.estimated_version(), code.version());
+    }
+}
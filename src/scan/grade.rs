@@ -0,0 +1,545 @@
+//! Print quality grading in the manner of ISO/IEC 15415.
+//!
+//! The symbol is located and decoded like in `scan`, then the grey levels of
+//! the image are measured at every module through a circular aperture of 0.8
+//! modules, and each parameter is graded from A (best) to F (fail):
+//!
+//! - Symbol contrast: the difference between the highest and the lowest
+//!   reflectance, quiet zone included.
+//! - Modulation: how far each module is from the global threshold halfway
+//!   between them, relative to the symbol contrast.
+//! - Reflectance margin: like modulation, but measured towards the color the
+//!   module should have, so that wrong modules count as zero.
+//! - Fixed pattern damage: wrong modules in the finder patterns with their
+//!   separators, and in the timing patterns.
+//! - Axial non-uniformity: how much the module pitch differs between the two
+//!   axes.
+//! - Grid non-uniformity: how far the alignment patterns are from where a
+//!   regular grid through the finder patterns puts them, in modules.
+//! - Unused error correction: the share of each block's correction capacity
+//!   left after decoding.
+//! - Decode: whether the symbol decodes at all.
+//!
+//! A symbol which is located but does not decode is graded F for decode,
+//! reflectance margin and unused error correction, which need the decoded
+//! data. Its modulation is still graded if the format information can be
+//! read, and its fixed pattern damage is measured against the function
+//! patterns of the estimated version.
+//!
+//! Modulation and reflectance margin are graded per codeword, from its worst
+//! module, and the codeword grades are combined with the error correction
+//! capacity: at each grade level, the codewords below it are counted as
+//! erasures, and the symbol gets the best level whose remaining capacity
+//! grades at least as well. The overall grade is the lowest of all. Only
+//! dark-on-light symbols are graded, from a single image.
+
+use std::fmt;
+
+use image::{DynamicImage, GrayImage};
+
+use crate::bch;
+use crate::canvas::Canvas;
+use crate::cast::As;
+use crate::decode::Decoded;
+use crate::ec;
+use crate::provenance::{CodewordKind, ModuleRole, ProvenanceMap};
+use crate::scan::binarize::{Binarizer, HybridBlockMean, Otsu, Sauvola};
+use crate::scan::finder::{find_finder_patterns, find_triples, FinderTriple};
+use crate::scan::sample::Grid;
+use crate::scan::{candidate_versions, locate_symbol, BitMatrix, Point};
+use crate::types::{Color, EcLevel};
+
+/// The width of the quiet zone included in the reflectance measurements, in
+/// modules.
+const QUIET_ZONE: i16 = 4;
+
+/// The diameter of the measuring aperture, in modules.
+const APERTURE: f64 = 0.8;
+
+/// The limits of the grades of modulation and reflectance margin.
+const MODULATION_LIMITS: [f64; 4] = [0.50, 0.40, 0.30, 0.20];
+
+/// The limits of the grades of unused error correction.
+const UNUSED_EC_LIMITS: [f64; 4] = [0.62, 0.50, 0.37, 0.25];
+
+/// A grade, from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Grade {
+    F,
+    D,
+    C,
+    B,
+    A,
+}
+
+impl Grade {
+    /// The grades which can be passed, from best to worst.
+    const PASSING: [Self; 4] = [Self::A, Self::B, Self::C, Self::D];
+
+    /// The grade of a value for which higher is better, given the lowest
+    /// values which still get A, B, C and D.
+    fn at_least(value: f64, limits: [f64; 4]) -> Self {
+        Self::PASSING
+            .into_iter()
+            .zip(limits)
+            .find_map(|(grade, limit)| (value >= limit).then_some(grade))
+            .unwrap_or(Self::F)
+    }
+
+    /// The grade of a value for which lower is better, given the highest
+    /// values which still get A, B, C and D.
+    fn at_most(value: f64, limits: [f64; 4]) -> Self {
+        Self::PASSING
+            .into_iter()
+            .zip(limits)
+            .find_map(|(grade, limit)| (value <= limit).then_some(grade))
+            .unwrap_or(Self::F)
+    }
+
+    /// The numeric grade, from 4 for A to 0 for F.
+    pub const fn points(self) -> u8 {
+        self as u8
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// The grade of one parameter, with the measured value where there is a
+/// single one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub value: Option<f64>,
+    pub grade: Grade,
+}
+
+impl Measurement {
+    const fn graded(grade: Grade) -> Self {
+        Self { value: None, grade }
+    }
+}
+
+/// The grades of a symbol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityReport {
+    /// In percent of the full grey scale.
+    pub symbol_contrast: Measurement,
+    pub modulation: Measurement,
+    pub reflectance_margin: Measurement,
+    pub fixed_pattern_damage: Measurement,
+    pub axial_nonuniformity: Measurement,
+
+    /// In modules.
+    pub grid_nonuniformity: Measurement,
+
+    /// The lowest share over all blocks.
+    pub unused_error_correction: Measurement,
+    pub decode: Measurement,
+
+    /// `None` if the symbol did not decode.
+    pub decoded: Option<Decoded>,
+}
+
+impl QualityReport {
+    /// Every parameter with its name, in the order of ISO/IEC 15415.
+    pub const fn parameters(&self) -> [(&'static str, Measurement); 8] {
+        [
+            ("symbol contrast", self.symbol_contrast),
+            ("modulation", self.modulation),
+            ("reflectance margin", self.reflectance_margin),
+            ("fixed pattern damage", self.fixed_pattern_damage),
+            ("axial non-uniformity", self.axial_nonuniformity),
+            ("grid non-uniformity", self.grid_nonuniformity),
+            ("unused error correction", self.unused_error_correction),
+            ("decode", self.decode),
+        ]
+    }
+
+    /// The lowest grade of all parameters.
+    pub fn overall(&self) -> Grade {
+        self.parameters().iter().map(|(_, m)| m.grade).min().unwrap_or(Grade::F)
+    }
+}
+
+impl fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, measurement) in self.parameters() {
+            match measurement.value {
+                Some(value) => writeln!(f, "{name:<24} {value:>7.2}  {}", measurement.grade)?,
+                None => writeln!(f, "{name:<24} {:>7}  {}", "", measurement.grade)?,
+            }
+        }
+        write!(f, "{:<24} {:>7}  {}", "overall", "", self.overall())
+    }
+}
+
+/// The mean grey level, in percent, of the pixels whose centres lie within
+/// `radius` of `center`, or of the nearest pixel if there are none. `None`
+/// outside the image.
+fn reflectance(image: &GrayImage, center: Point, radius: f64) -> Option<f64> {
+    let (w, h) = (f64::from(image.width()), f64::from(image.height()));
+    if center.x < 0.0 || center.y < 0.0 || center.x >= w || center.y >= h {
+        return None;
+    }
+    let (mut sum, mut count) = (0.0, 0_u32);
+    let (low_y, high_y) = ((center.y - radius).floor().max(0.0), (center.y + radius).ceil().min(h));
+    let (low_x, high_x) = ((center.x - radius).floor().max(0.0), (center.x + radius).ceil().min(w));
+    for y in low_y as u32..high_y as u32 {
+        for x in low_x as u32..high_x as u32 {
+            let pixel = Point::new(f64::from(x) + 0.5, f64::from(y) + 0.5);
+            if pixel.distance(center) <= radius {
+                sum += f64::from(image.get_pixel(x, y)[0]);
+                count += 1;
+            }
+        }
+    }
+    if count == 0 {
+        sum = f64::from(image.get_pixel(center.x as u32, center.y as u32)[0]);
+        count = 1;
+    }
+    Some(sum / f64::from(count) * 100.0 / 255.0)
+}
+
+/// Combines per-codeword grades into a symbol grade, see the module
+/// documentation. `blocks` holds the grades of the codewords of every block.
+fn grade_codewords(blocks: &[Vec<Grade>], capacity: usize) -> Grade {
+    Grade::PASSING
+        .into_iter()
+        .map(|level| {
+            let unused = blocks
+                .iter()
+                .map(|block| {
+                    let erasures = block.iter().filter(|g| **g < level).count();
+                    1.0 - erasures as f64 / capacity as f64
+                })
+                .fold(1.0, f64::min);
+            level.min(Grade::at_least(unused, UNUSED_EC_LIMITS))
+        })
+        .max()
+        .unwrap_or(Grade::F)
+}
+
+/// Locates and decodes the first dark-on-light symbol in `image`, together
+/// with the matrix and finder patterns it was found with. If no symbol
+/// decodes, the first one located is returned undecoded, with the grid of
+/// the version estimated from its finder patterns.
+fn locate(image: &GrayImage) -> Option<(BitMatrix, FinderTriple, Grid, Option<Decoded>)> {
+    let binarizers: [&dyn Binarizer; 3] = [&HybridBlockMean, &Sauvola::default(), &Otsu];
+    let mut undecoded = None;
+    for binarizer in binarizers {
+        let matrix = binarizer.binarize(image);
+        let patterns = find_finder_patterns(&matrix);
+        for triple in find_triples(&matrix, &patterns) {
+            if let Some((grid, decoded)) = locate_symbol(&matrix, &triple) {
+                return Some((matrix, triple, grid, Some(decoded)));
+            }
+            if undecoded.is_none()
+                && let Some(version) = candidate_versions(&triple).next()
+            {
+                let grid = Grid::locate(&matrix, &triple, version);
+                undecoded = Some((matrix.clone(), triple, grid, None));
+            }
+        }
+    }
+    undecoded
+}
+
+/// Grades the print quality of the first QR code found in an image. Returns
+/// `None` if no symbol can be located, which would be graded F overall.
+pub fn grade(image: &DynamicImage) -> Option<QualityReport> {
+    let image = image.to_luma8();
+    let (matrix, triple, grid, decoded) = locate(&image)?;
+    let version = grid.version;
+    let width = version.width();
+    let w = width.as_usize();
+
+    // Mirrored symbols were decoded transposed, and so were their ideal
+    // matrix and module roles. Without a decoded symbol only the function
+    // patterns are known, which look the same mirrored.
+    let mirrored = decoded.as_ref().is_some_and(|decoded| decoded.mirrored);
+    let transpose = |x: i16, y: i16| if mirrored { (y, x) } else { (x, y) };
+    let ideal = match &decoded {
+        Some(decoded) => decoded.ideal_matrix(),
+        None => {
+            let mut canvas = Canvas::new(version, EcLevel::L);
+            canvas.draw_all_functional_patterns();
+            canvas.into_colors()
+        }
+    };
+    let ideal_dark = |x: i16, y: i16| {
+        let (x, y) = transpose(x, y);
+        ideal[(y * width + x).as_usize()] == Color::Dark
+    };
+    let ec_level = match &decoded {
+        Some(decoded) => Some(decoded.ec_level),
+        None => {
+            let format_info = bch::read_format_info(&grid.sample(&matrix), w).ok();
+            format_info.map(|info| info.value.ec_level)
+        }
+    };
+    // The layout of the codewords depends on the EC level, but the roles of
+    // the function patterns do not.
+    let layout_level = ec_level.unwrap_or(EcLevel::L);
+    let map = ProvenanceMap::new(version, layout_level).expect("valid version");
+    let role = |x: i16, y: i16| {
+        let (x, y) = transpose(x, y);
+        map.get(x, y)
+    };
+
+    // Reflectances of the symbol and its quiet zone.
+    let radius = APERTURE / 2.0 * grid.module_size;
+    let measure = |x: i16, y: i16| {
+        let center = Point::new(f64::from(x) + 0.5, f64::from(y) + 0.5);
+        reflectance(&image, grid.transform.map(center), radius)
+    };
+    let mut quiet_zone = Vec::new();
+    let mut symbol = Vec::with_capacity(w * w);
+    for y in -QUIET_ZONE..width + QUIET_ZONE {
+        for x in -QUIET_ZONE..width + QUIET_ZONE {
+            let inside = (0..width).contains(&x) && (0..width).contains(&y);
+            match (inside, measure(x, y)) {
+                (true, r) => symbol.push(r.unwrap_or(100.0)),
+                (false, Some(r)) => quiet_zone.push(r),
+                (false, None) => {}
+            }
+        }
+    }
+    let all = symbol.iter().chain(&quiet_zone).copied();
+    let r_max = all.clone().fold(f64::MIN, f64::max);
+    let r_min = all.fold(f64::MAX, f64::min);
+    let contrast = r_max - r_min;
+    let threshold = (r_max + r_min) / 2.0;
+    let at = |x: i16, y: i16| symbol[(y * width + x).as_usize()];
+    let measured_dark = |x: i16, y: i16| at(x, y) < threshold;
+
+    // Modulation and reflectance margin, graded per codeword.
+    let (data_lengths, ec_length) = ec::block_layout(version, layout_level).expect("valid version");
+    let capacity = ec_length - ec::misdecode_protection(version, layout_level);
+    let mut modulation = data_lengths
+        .iter()
+        .map(|len| vec![Grade::A; len + ec_length])
+        .collect::<Vec<_>>();
+    let mut margin = modulation.clone();
+    for y in 0..width {
+        for x in 0..width {
+            let ModuleRole::Codeword {
+                kind, block, index, ..
+            } = role(x, y)
+            else {
+                continue;
+            };
+            let offset = match kind {
+                CodewordKind::Data => 0,
+                CodewordKind::ErrorCorrection => data_lengths[block],
+            };
+            let distance = (at(x, y) - threshold) * 2.0 / contrast;
+            let signed = if ideal_dark(x, y) { -distance } else { distance };
+            for (grades, value) in [(&mut modulation, distance.abs()), (&mut margin, signed)] {
+                let grade = &mut grades[block][offset + index];
+                *grade = (*grade).min(Grade::at_least(value, MODULATION_LIMITS));
+            }
+        }
+    }
+    let modulation = match ec_level {
+        Some(_) => grade_codewords(&modulation, capacity),
+        None => Grade::F,
+    };
+    let margin = match decoded {
+        Some(_) => grade_codewords(&margin, capacity),
+        None => Grade::F,
+    };
+
+    // Fixed pattern damage: every finder pattern with its separator, and the
+    // timing patterns.
+    let wrong = |x: i16, y: i16| measured_dark(x, y) != ideal_dark(x, y);
+    let finder_grades = [(0, 0), (width - 8, 0), (0, width - 8)].map(|(fx, fy)| {
+        let damaged = (fy..fy + 8)
+            .flat_map(|y| (fx..fx + 8).map(move |x| (x, y)))
+            .filter(|(x, y)| matches!(role(*x, *y), ModuleRole::Finder | ModuleRole::Separator))
+            .filter(|(x, y)| wrong(*x, *y))
+            .count();
+        Grade::at_most(damaged as f64, [0.0, 1.0, 2.0, 3.0])
+    });
+    let timing = (0..width)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|(x, y)| role(*x, *y) == ModuleRole::Timing)
+        .map(|(x, y)| wrong(x, y))
+        .collect::<Vec<_>>();
+    let timing_damage = timing.iter().filter(|w| **w).count() as f64 / timing.len() as f64;
+    let fixed_pattern_damage = finder_grades
+        .into_iter()
+        .chain([Grade::at_most(timing_damage, [0.0, 0.07, 0.11, 0.14])])
+        .min()
+        .unwrap_or(Grade::F);
+
+    // Axial non-uniformity from the average module pitch along each axis.
+    let t = |x: f64, y: f64| grid.transform.map(Point::new(x, y));
+    let last = f64::from(width) - 0.5;
+    let pitch = |along_x: bool| {
+        (0..width)
+            .map(|i| {
+                let c = f64::from(i) + 0.5;
+                if along_x {
+                    t(0.5, c).distance(t(last, c))
+                } else {
+                    t(c, 0.5).distance(t(c, last))
+                }
+            })
+            .sum::<f64>()
+            / f64::from(width)
+            / (last - 0.5)
+    };
+    let (x_pitch, y_pitch) = (pitch(true), pitch(false));
+    let axial = (x_pitch - y_pitch).abs() / ((x_pitch + y_pitch) / 2.0);
+
+    // Grid non-uniformity against the regular grid through the finders.
+    let (tl, tr, bl) = (triple.top_left.center, triple.top_right.center, triple.bottom_left.center);
+    let far = f64::from(width) - 7.0;
+    let regular = |x: f64, y: f64| {
+        let (u, v) = ((x - 3.5) / far, (y - 3.5) / far);
+        Point::new(
+            tl.x + (tr.x - tl.x) * u + (bl.x - tl.x) * v,
+            tl.y + (tr.y - tl.y) * u + (bl.y - tl.y) * v,
+        )
+    };
+    let module = (x_pitch + y_pitch) / 2.0;
+    let grid_deviation = grid
+        .find_alignment_patterns(&matrix)
+        .iter()
+        .filter_map(|((x, y), found)| {
+            let ideal = regular(f64::from(*x) + 0.5, f64::from(*y) + 0.5);
+            found.map(|p| p.distance(ideal) / module)
+        })
+        .fold(0.0, f64::max);
+
+    // Unused error correction of the worst block. Erasures cost one
+    // codeword of the capacity and errors two; `corrections` counts errors
+    // only.
+    let unused = decoded.as_ref().map_or(0.0, |decoded| {
+        decoded
+            .corrections
+            .iter()
+            .zip(&decoded.erasures)
+            .map(|(errors, erasures)| 1.0 - (2 * errors + erasures) as f64 / capacity as f64)
+            .fold(1.0, f64::min)
+            .max(0.0)
+    });
+
+    Some(QualityReport {
+        symbol_contrast: Measurement {
+            value: Some(contrast),
+            grade: Grade::at_least(contrast, [70.0, 55.0, 40.0, 20.0]),
+        },
+        modulation: Measurement::graded(modulation),
+        reflectance_margin: Measurement::graded(margin),
+        fixed_pattern_damage: Measurement::graded(fixed_pattern_damage),
+        axial_nonuniformity: Measurement {
+            value: Some(axial),
+            grade: Grade::at_most(axial, [0.06, 0.08, 0.10, 0.12]),
+        },
+        grid_nonuniformity: Measurement {
+            value: Some(grid_deviation),
+            grade: Grade::at_most(grid_deviation, [0.38, 0.50, 0.63, 0.75]),
+        },
+        unused_error_correction: Measurement {
+            value: Some(unused),
+            grade: Grade::at_least(unused, UNUSED_EC_LIMITS),
+        },
+        decode: Measurement::graded(if decoded.is_some() { Grade::A } else { Grade::F }),
+        decoded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    use crate::qr_code::QrCode;
+    use crate::scan::tests::render;
+    use crate::types::EcLevel;
+
+    fn sample_code() -> QrCode {
+        QrCode::with_error_correction_level(b"print quality".repeat(4), EcLevel::Q).unwrap()
+    }
+
+    fn grade_image(image: GrayImage) -> Option<QualityReport> {
+        grade(&DynamicImage::ImageLuma8(image))
+    }
+
+    /// Paints module (x, y) of the symbol in a render with `module_size`
+    /// pixels per module.
+    fn paint(image: &mut GrayImage, (x, y): (u32, u32), module_size: u32, value: u8) {
+        for py in (4 + y) * module_size..(5 + y) * module_size {
+            for px in (4 + x) * module_size..(5 + x) * module_size {
+                image.put_pixel(px, py, Luma([value]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_clean_render() {
+        let code = sample_code();
+        let report = grade_image(render(&code, 6)).unwrap();
+        assert_eq!(report.overall(), Grade::A);
+        assert_eq!(report.symbol_contrast.value, Some(100.0));
+        assert_eq!(report.unused_error_correction.value, Some(1.0));
+        assert_eq!(report.decoded.unwrap().data, b"print quality".repeat(4));
+    }
+
+    #[test]
+    fn test_low_contrast() {
+        let code = sample_code();
+        let mut image = render(&code, 6);
+        for pixel in image.pixels_mut() {
+            pixel.0[0] = if pixel.0[0] < 128 { 90 } else { 170 };
+        }
+        let report = grade_image(image).unwrap();
+        assert!(report.symbol_contrast.grade < Grade::A);
+        assert!(report.symbol_contrast.value.unwrap() < 35.0);
+        assert_eq!(report.decode.grade, Grade::A);
+    }
+
+    #[test]
+    fn test_damaged_finder() {
+        let code = sample_code();
+        let mut image = render(&code, 6);
+        // A module of the separator of the top left finder pattern.
+        paint(&mut image, (7, 0), 6, 0);
+        let report = grade_image(image).unwrap();
+        assert_eq!(report.fixed_pattern_damage.grade, Grade::B);
+        assert_eq!(report.decode.grade, Grade::A);
+        assert!(report.overall() < Grade::A);
+    }
+
+    #[test]
+    fn test_undecodable() {
+        let code = sample_code();
+        let width = code.width().as_u32();
+        let mut image = render(&code, 6);
+        // Cover the middle of the symbol with a dark pattern, leaving the
+        // finder patterns and the format information intact.
+        for y in 9..width - 9 {
+            for x in 9..width - 9 {
+                if (x + 3 * y) % 5 < 2 {
+                    paint(&mut image, (x, y), 6, 0);
+                }
+            }
+        }
+        let report = grade_image(image).unwrap();
+        assert!(report.decoded.is_none());
+        assert_eq!(report.decode.grade, Grade::F);
+        assert_eq!(report.unused_error_correction.grade, Grade::F);
+        assert_eq!(report.overall(), Grade::F);
+        // The modulation is still graded through the format information.
+        assert_eq!(report.modulation.grade, Grade::A);
+    }
+
+    #[test]
+    fn test_no_symbol() {
+        assert_eq!(grade_image(GrayImage::from_pixel(100, 100, Luma([255]))), None);
+    }
+}
@@ -0,0 +1,455 @@
+//! Reading QR codes from photos.
+//!
+//! The image is first turned into a `BitMatrix` by one of the binarizers in
+//! `binarize`, then `finder` locates the finder patterns of the symbols in
+//! it and `sample` reads their module grids, which are finally decoded by
+//! `crate::decode`. `scan` runs all of these steps, and `grade` grades the
+//! print quality of a symbol from them.
+
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::cast::As;
+use crate::decode::{decode_matrix, Decoded};
+use crate::scan::binarize::{Binarizer, HybridBlockMean, Otsu, Sauvola};
+use crate::scan::finder::{find_finder_patterns, find_triples, FinderPattern, FinderTriple};
+use crate::scan::sample::Grid;
+use crate::types::Version;
+
+pub mod binarize;
+pub mod finder;
+pub mod grade;
+pub mod sample;
+
+/// A position in an image, in pixels. The centre of pixel (x, y) is at
+/// (x + 0.5, y + 0.5).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Point {
+    pub const fn new(x: f64, y: f64) -> Self {
+        Self { x, y }
+    }
+
+    pub fn distance(self, other: Self) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+}
+
+/// Whether `point` lies inside the convex quadrilateral `quad`, whose corners
+/// are given in rotational order.
+fn quad_contains(quad: &[Point; 4], point: Point) -> bool {
+    let sides = [0, 1, 2, 3].map(|i| {
+        let (a, b) = (quad[i], quad[(i + 1) % 4]);
+        (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x)
+    });
+    sides.iter().all(|side| *side >= 0.0) || sides.iter().all(|side| *side <= 0.0)
+}
+
+/// A black and white image, as produced by a binarizer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitMatrix {
+    width: u32,
+    height: u32,
+
+    /// `true` for dark pixels, in left-to-right, then top-to-bottom order.
+    bits: Vec<bool>,
+}
+
+impl BitMatrix {
+    /// Creates an all-light matrix.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            bits: vec![false; width.as_usize() * height.as_usize()],
+        }
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        debug_assert!(x < self.width && y < self.height, "({x}, {y}) is out of bounds");
+        y.as_usize() * self.width.as_usize() + x.as_usize()
+    }
+
+    pub fn is_dark(&self, x: u32, y: u32) -> bool {
+        self.bits[self.index(x, y)]
+    }
+
+    /// Like `is_dark`, but returns `None` outside the matrix.
+    pub fn get(&self, x: i64, y: i64) -> Option<bool> {
+        let x = u32::try_from(x).ok().filter(|x| *x < self.width)?;
+        let y = u32::try_from(y).ok().filter(|y| *y < self.height)?;
+        Some(self.is_dark(x, y))
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, dark: bool) {
+        let index = self.index(x, y);
+        self.bits[index] = dark;
+    }
+
+    /// Swaps dark and light, to read light-on-dark symbols.
+    pub fn inverted(&self) -> Self {
+        Self {
+            bits: self.bits.iter().map(|dark| !dark).collect(),
+            ..*self
+        }
+    }
+
+    /// Converts the matrix back into an image with black and white pixels,
+    /// e.g. to compare binarizers.
+    pub fn to_image(&self) -> GrayImage {
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            Luma([if self.is_dark(x, y) { 0 } else { 255 }])
+        })
+    }
+}
+
+/// A symbol read from an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanResult {
+    /// The payload, version, EC level, mask and corrections of the symbol.
+    pub decoded: Decoded,
+
+    /// The payload as text, if it is valid UTF-8.
+    pub text: Option<String>,
+
+    /// The outer corners of the symbol, clockwise from the top left.
+    pub corners: [Point; 4],
+
+    /// Whether the symbol is light on dark. Whether it is mirror-imaged is
+    /// reported by `Decoded::mirrored`.
+    pub inverted: bool,
+}
+
+impl ScanResult {
+    /// The centre of the symbol in the image.
+    pub fn center(&self) -> Point {
+        let [tl, tr, br, bl] = self.corners;
+        Point::new((tl.x + tr.x + br.x + bl.x) / 4.0, (tl.y + tr.y + br.y + bl.y) / 4.0)
+    }
+}
+
+/// The versions tried for a triple: the estimated one first, then its
+/// neighbours, as the estimate is sometimes off by one.
+fn candidate_versions(triple: &FinderTriple) -> impl Iterator<Item = Version> {
+    let Version::Normal(estimate) = triple.estimated_version();
+    [estimate, estimate + 1, estimate - 1]
+        .into_iter()
+        .filter(|v| (1..=40).contains(v))
+        .map(Version::Normal)
+}
+
+/// Locates the grid of the symbol whose finder patterns are `triple`, and
+/// decodes it.
+fn locate_symbol(matrix: &BitMatrix, triple: &FinderTriple) -> Option<(Grid, Decoded)> {
+    candidate_versions(triple).find_map(|version| {
+        let grid = Grid::locate(matrix, triple, version);
+        let width = grid.width();
+        // Only symbols from version 7 on have several alignment patterns to
+        // sample between.
+        let decoded = decode_matrix(&grid.sample(matrix), width).ok().or_else(|| {
+            matches!(version, Version::Normal(7..))
+                .then(|| decode_matrix(&grid.sample_piecewise(matrix), width).ok())
+                .flatten()
+        });
+        decoded.map(|decoded| (grid, decoded))
+    })
+}
+
+/// Locates and decodes the symbol whose finder patterns are `triple`.
+fn read_triple(matrix: &BitMatrix, triple: &FinderTriple) -> Option<ScanResult> {
+    locate_symbol(matrix, triple).map(|(grid, decoded)| ScanResult {
+        text: String::from_utf8(decoded.data.clone()).ok(),
+        decoded,
+        corners: grid.corners(),
+        inverted: false,
+    })
+}
+
+/// Reads the symbols whose finder patterns `patterns` were found in `matrix`,
+/// adds those not already in `results`, and returns how many were read in
+/// total. Triples enclosing patterns of other symbols are tried last, and
+/// patterns used by a symbol are not reused for another.
+fn read_symbols(
+    matrix: &BitMatrix,
+    patterns: &[FinderPattern],
+    inverted: bool,
+    results: &mut Vec<ScanResult>,
+) -> usize {
+    let mut triples = find_triples(matrix, patterns)
+        .into_iter()
+        .map(|triple| {
+            let members = triple.patterns();
+            let enclosed = patterns
+                .iter()
+                .filter(|p| !members.contains(p) && triple.encloses(p.center))
+                .count();
+            (enclosed, triple)
+        })
+        .collect::<Vec<_>>();
+    // The sort is stable, so triples keep their order by skew otherwise.
+    triples.sort_by_key(|(enclosed, _)| *enclosed);
+
+    let mut used = Vec::new();
+    let mut count = 0;
+    for (_, triple) in triples {
+        let members = triple.patterns();
+        if members.iter().any(|p| used.contains(p)) {
+            continue;
+        }
+        let Some(result) = read_triple(matrix, &triple) else {
+            continue;
+        };
+        let result = ScanResult { inverted, ..result };
+        used.extend(members);
+        count += 1;
+        let center = result.center();
+        if !results.iter().any(|r| quad_contains(&r.corners, center)) {
+            results.push(result);
+        }
+    }
+    count
+}
+
+/// Sorts symbols into reading order: in rows from top to bottom, each from
+/// left to right. A symbol belongs to the row of the topmost symbol whose
+/// vertical extent contains its centre.
+fn sort_reading_order(results: &mut Vec<ScanResult>) {
+    results.sort_by(|a, b| a.center().y.total_cmp(&b.center().y));
+    let mut rows: Vec<Vec<ScanResult>> = Vec::new();
+    for result in results.drain(..) {
+        let y = result.center().y;
+        let row = rows.iter_mut().find(|row| {
+            let ys = row[0].corners.map(|p| p.y);
+            let top = ys.iter().copied().fold(f64::MAX, f64::min);
+            let bottom = ys.iter().copied().fold(f64::MIN, f64::max);
+            (top..=bottom).contains(&y)
+        });
+        match row {
+            Some(row) => row.push(result),
+            None => rows.push(vec![result]),
+        }
+    }
+    for mut row in rows {
+        row.sort_by(|a, b| a.center().x.total_cmp(&b.center().x));
+        results.extend(row);
+    }
+}
+
+/// Reads all QR codes in an image, in reading order. Every binarizer is
+/// tried, from the most to the least robust against uneven lighting, and
+/// each symbol is reported once even if several of them could read it. If no
+/// finder pattern is found in a binarized image, it is inverted to look for
+/// light-on-dark symbols.
+pub fn scan(image: &DynamicImage) -> Vec<ScanResult> {
+    let image = image.to_luma8();
+    let binarizers: [&dyn Binarizer; 3] = [&HybridBlockMean, &Sauvola::default(), &Otsu];
+    let mut results = Vec::new();
+    for binarizer in binarizers {
+        let matrix = binarizer.binarize(&image);
+        let patterns = find_finder_patterns(&matrix);
+        if patterns.is_empty() {
+            let inverted = matrix.inverted();
+            read_symbols(&inverted, &find_finder_patterns(&inverted), true, &mut results);
+        } else {
+            read_symbols(&matrix, &patterns, false, &mut results);
+        }
+    }
+    sort_reading_order(&mut results);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::degrade::{Degradation, Rng};
+    use crate::qr_code::QrCode;
+    use crate::scan::sample::Perspective;
+    use crate::types::EcLevel;
+
+    /// Renders `code` with `module_size` pixels per module and the standard
+    /// quiet zone of 4 modules.
+    pub(super) fn render(code: &QrCode, module_size: u32) -> GrayImage {
+        code.render::<Luma<u8>>().module_dimensions(module_size, module_size).build()
+    }
+
+    /// Maps the corners of `image` onto `to` in a `width × height` image,
+    /// with light pixels where there is no source.
+    pub(super) fn warp(image: &GrayImage, to: [Point; 4], width: u32, height: u32) -> GrayImage {
+        let (w, h) = (f64::from(image.width()), f64::from(image.height()));
+        let from = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)].map(|(x, y)| Point::new(x, y));
+        let inverse = Perspective::from_quads(to, from);
+        GrayImage::from_fn(width, height, |x, y| {
+            let p = inverse.map(Point::new(f64::from(x) + 0.5, f64::from(y) + 0.5));
+            if p.x < 0.0 || p.y < 0.0 || p.x >= w || p.y >= h {
+                Luma([255])
+            } else {
+                *image.get_pixel(p.x as u32, p.y as u32)
+            }
+        })
+    }
+
+    /// The corners of `code` in `render(code, module_size)`.
+    fn rendered_corners(code: &QrCode, module_size: u32) -> [Point; 4] {
+        let (low, high) = (4.0, 4.0 + code.width() as f64);
+        [(low, low), (high, low), (high, high), (low, high)]
+            .map(|(x, y)| Point::new(x * f64::from(module_size), y * f64::from(module_size)))
+    }
+
+    /// Scans `image` and checks that it holds exactly `code`, made from
+    /// `data`, with its corners within half a module of `corners`.
+    fn check_scan(image: GrayImage, code: &QrCode, data: &[u8], corners: [Point; 4], module: f64) {
+        let results = scan(&DynamicImage::ImageLuma8(image));
+        assert_eq!(results.len(), 1);
+        let result = &results[0];
+        assert_eq!(result.decoded.data, data);
+        assert_eq!(result.decoded.version, code.version());
+        assert_eq!(result.decoded.ec_level, code.error_correction_level());
+        assert!(!result.inverted && !result.decoded.mirrored);
+        for (found, expected) in result.corners.iter().zip(corners) {
+            assert!(found.distance(expected) < module / 2.0, "{found:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn test_scan_renders() {
+        let cases = [
+            (1, EcLevel::L),
+            (40, EcLevel::M),
+            (100, EcLevel::Q),
+            (130, EcLevel::H),
+            (300, EcLevel::L),
+        ];
+        for (len, ec_level) in cases {
+            let data = b"scan".repeat(len);
+            let code = QrCode::with_error_correction_level(&data, ec_level).unwrap();
+            let corners = rendered_corners(&code, 3);
+            check_scan(render(&code, 3), &code, &data, corners, 3.0);
+        }
+    }
+
+    #[test]
+    fn test_scan_warped() {
+        let data = b"a symbol seen at an angle".repeat(7);
+        let code = QrCode::with_error_correction_level(&data, EcLevel::M).unwrap();
+        assert!(matches!(code.version(), Version::Normal(7..)));
+        let image = render(&code, 5);
+        let size = f64::from(image.width());
+        let to = [(40.0, 20.0), (size + 10.0, 50.0), (size + 30.0, size + 40.0), (10.0, size)]
+            .map(|(x, y)| Point::new(x, y));
+        let from = [(0.0, 0.0), (size, 0.0), (size, size), (0.0, size)]
+            .map(|(x, y)| Point::new(x, y));
+        let transform = Perspective::from_quads(from, to);
+        let warped = warp(&image, to, image.width() + 60, image.height() + 60);
+        let corners = rendered_corners(&code, 5).map(|p| transform.map(p));
+        check_scan(warped, &code, &data, corners, 5.0);
+    }
+
+    #[test]
+    fn test_scan_rotated() {
+        let data = b"rotated";
+        let code = QrCode::with_error_correction_level(data, EcLevel::Q).unwrap();
+        let image = render(&code, 6);
+        let side = code.width() as f64 * 6.0;
+        for seed in 0..8 {
+            let rotation = Degradation::Rotation { max_degrees: 180.0 };
+            let rotated = rotation.apply(&image, &mut Rng::new(seed));
+            let results = scan(&DynamicImage::ImageLuma8(rotated));
+            assert_eq!(results.len(), 1);
+            let result = &results[0];
+            assert_eq!(result.decoded.data, data);
+            assert_eq!(result.decoded.version, code.version());
+            assert_eq!(result.decoded.ec_level, EcLevel::Q);
+            // The corners still form a square of the rendered size.
+            for i in 0..4 {
+                let edge = result.corners[i].distance(result.corners[(i + 1) % 4]);
+                assert!((edge - side).abs() < 6.0, "{edge} != {side}");
+            }
+        }
+    }
+
+    /// The part of an image a symbol occupies, clockwise from the top left.
+    type Area = [Point; 4];
+
+    /// Six symbols in two rows of three, with different payloads and
+    /// versions, and the area of the image each of them occupies.
+    fn symbol_grid() -> (GrayImage, Vec<(Vec<u8>, Area)>) {
+        let payloads = (0..6).map(|i| format!("symbol {i} ").repeat(1 + 4 * i).into_bytes());
+        let codes = payloads
+            .map(|data| (QrCode::with_error_correction_level(&data, EcLevel::M).unwrap(), data))
+            .collect::<Vec<_>>();
+        let cell = codes.iter().map(|(code, _)| render(code, 3).width()).max().unwrap();
+        let mut image = GrayImage::from_pixel(3 * cell, 2 * cell, Luma([255]));
+        let mut symbols = Vec::new();
+        for (i, (code, data)) in codes.into_iter().enumerate() {
+            let (left, top) = ((i.as_u32() % 3) * cell, (i.as_u32() / 3) * cell);
+            image::imageops::overlay(&mut image, &render(&code, 3), left.into(), top.into());
+            let (left, top, right, bottom) =
+                (f64::from(left), f64::from(top), f64::from(left + cell), f64::from(top + cell));
+            let area = [(left, top), (right, top), (right, bottom), (left, bottom)]
+                .map(|(x, y)| Point::new(x, y));
+            symbols.push((data, area));
+        }
+        (image, symbols)
+    }
+
+    #[test]
+    fn test_several_symbols() {
+        let (image, symbols) = symbol_grid();
+        let binarizers: [&dyn Binarizer; 3] = [&HybridBlockMean, &Sauvola::default(), &Otsu];
+        for binarizer in binarizers {
+            let mut results = Vec::new();
+            let matrix = binarizer.binarize(&image);
+            let patterns = find_finder_patterns(&matrix);
+            let count = read_symbols(&matrix, &patterns, false, &mut results);
+            assert_eq!(count, symbols.len());
+            sort_reading_order(&mut results);
+            assert_eq!(results.len(), symbols.len());
+            for (result, (data, area)) in results.iter().zip(&symbols) {
+                assert_eq!(&result.decoded.data, data);
+                // A triple made of patterns of different symbols would reach
+                // outside the area of the symbol.
+                assert!(result.corners.iter().all(|p| quad_contains(area, *p)));
+            }
+        }
+
+        let results = scan(&DynamicImage::ImageLuma8(image));
+        let data = results.iter().map(|r| &r.decoded.data).collect::<Vec<_>>();
+        assert_eq!(data, symbols.iter().map(|(data, _)| data).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_inverted() {
+        let data = b"light on dark";
+        let code = QrCode::with_error_correction_level(data, EcLevel::M).unwrap();
+        let mut image = render(&code, 4);
+        image::imageops::invert(&mut image);
+        let results = scan(&DynamicImage::ImageLuma8(image));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].decoded.data, data);
+        assert!(results[0].inverted);
+        assert!(!results[0].decoded.mirrored);
+    }
+
+    #[test]
+    fn test_mirrored() {
+        let data = b"printed on the back of glass";
+        let code = QrCode::with_error_correction_level(data, EcLevel::Q).unwrap();
+        let image = image::imageops::flip_horizontal(&render(&code, 4));
+        let results = scan(&DynamicImage::ImageLuma8(image));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].decoded.data, data);
+        assert!(results[0].decoded.mirrored);
+        assert!(!results[0].inverted);
+    }
+}
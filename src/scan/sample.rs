@@ -0,0 +1,425 @@
+//! Mapping the module grid of a symbol onto an image and sampling it.
+//!
+//! The three finder centres and the centre of the bottom-right alignment
+//! pattern define a perspective transform from module coordinates to image
+//! coordinates. Symbols without that alignment pattern, or where it cannot be
+//! found, fall back to the affine transform defined by the finders alone.
+//!
+//! Large symbols printed on curved or creased surfaces are not described well
+//! by a single transform. `Grid::sample_piecewise` therefore locates every
+//! alignment pattern and uses a separate transform for each cell between
+//! them.
+
+use crate::canvas::alignment_pattern_centers;
+use crate::cast::As;
+use crate::scan::finder::{cross_check, FinderTriple, Runs};
+use crate::scan::{BitMatrix, Point};
+use crate::types::{Color, Version};
+
+/// The search radii tried around the expected position of the bottom-right
+/// alignment pattern, in modules.
+const ALIGNMENT_SEARCH_RADII: [f64; 3] = [4.0, 8.0, 16.0];
+
+/// The search radius around the expected position of the other alignment
+/// patterns, in modules. The global transform predicts them well already.
+const LOCAL_ALIGNMENT_SEARCH_RADIUS: f64 = 3.0;
+
+/// A projective transform of the plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Perspective {
+    /// Maps the column vector (x, y, 1) to homogeneous coordinates.
+    m: [[f64; 3]; 3],
+}
+
+impl Perspective {
+    /// The transform mapping the unit square (0, 0), (1, 0), (1, 1), (0, 1) to
+    /// the given quadrilateral.
+    fn from_unit_square([p0, p1, p2, p3]: [Point; 4]) -> Self {
+        let dx3 = p0.x - p1.x + p2.x - p3.x;
+        let dy3 = p0.y - p1.y + p2.y - p3.y;
+        let (g, h) = if dx3 == 0.0 && dy3 == 0.0 {
+            (0.0, 0.0)
+        } else {
+            let (dx1, dx2) = (p1.x - p2.x, p3.x - p2.x);
+            let (dy1, dy2) = (p1.y - p2.y, p3.y - p2.y);
+            let denominator = dx1 * dy2 - dx2 * dy1;
+            (
+                (dx3 * dy2 - dx2 * dy3) / denominator,
+                (dx1 * dy3 - dx3 * dy1) / denominator,
+            )
+        };
+        Self {
+            m: [
+                [p1.x - p0.x + g * p1.x, p3.x - p0.x + h * p3.x, p0.x],
+                [p1.y - p0.y + g * p1.y, p3.y - p0.y + h * p3.y, p0.y],
+                [g, h, 1.0],
+            ],
+        }
+    }
+
+    /// The inverse transform, up to a scale factor which does not matter in
+    /// homogeneous coordinates.
+    fn adjugate(&self) -> Self {
+        let m = &self.m;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        Self {
+            m: [
+                [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+                [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+                [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+            ],
+        }
+    }
+
+    fn then(&self, next: &Self) -> Self {
+        let mut m = [[0.0; 3]; 3];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| next.m[r][k] * self.m[k][c]).sum();
+            }
+        }
+        Self { m }
+    }
+
+    /// The transform mapping each corner of `from` to the same corner of `to`.
+    /// Corners are given in the same rotational order in both.
+    pub fn from_quads(from: [Point; 4], to: [Point; 4]) -> Self {
+        Self::from_unit_square(from).adjugate().then(&Self::from_unit_square(to))
+    }
+
+    pub fn map(&self, p: Point) -> Point {
+        let m = &self.m;
+        let w = m[2][0] * p.x + m[2][1] * p.y + m[2][2];
+        Point::new(
+            (m[0][0] * p.x + m[0][1] * p.y + m[0][2]) / w,
+            (m[1][0] * p.x + m[1][1] * p.y + m[1][2]) / w,
+        )
+    }
+}
+
+/// The centre of module (x, y) in module coordinates.
+fn module_center(x: i16, y: i16) -> Point {
+    Point::new(f64::from(x) + 0.5, f64::from(y) + 0.5)
+}
+
+/// Whether the three middle runs match the light ring, dark centre and light
+/// ring of an alignment pattern. The outer runs only have to exist, as the
+/// dark ring may merge with neighbouring dark modules.
+fn is_alignment_ratio(runs: &Runs, module_size: f64) -> bool {
+    runs[0] > 0
+        && runs[4] > 0
+        && runs[1..4].iter().all(|run| (f64::from(*run) - module_size).abs() < module_size * 0.5)
+}
+
+/// Whether the 5 × 5 modules around `center` look like an alignment pattern.
+/// `axes` are the image vectors of one module step along the rows and the
+/// columns of the symbol. One wrong module is tolerated.
+fn matches_alignment_template(matrix: &BitMatrix, center: Point, [u, v]: [Point; 2]) -> bool {
+    let mut mismatches = 0;
+    for j in -2_i16..=2 {
+        for i in -2_i16..=2 {
+            let (i_f, j_f) = (f64::from(i), f64::from(j));
+            let p = Point::new(center.x + i_f * u.x + j_f * v.x, center.y + i_f * u.y + j_f * v.y);
+            let expected = if i.abs().max(j.abs()) == 1 { Color::Light } else { Color::Dark };
+            if sample_at(matrix, p) != expected {
+                mismatches += 1;
+            }
+        }
+    }
+    mismatches <= 1
+}
+
+/// Looks for an alignment pattern within `radius` pixels of `estimate`, and
+/// returns the centre of the one nearest to it. `axes` are the image vectors
+/// of one module step along the rows and the columns of the symbol.
+pub fn find_alignment_pattern(
+    matrix: &BitMatrix,
+    estimate: Point,
+    axes: [Point; 2],
+    radius: f64,
+) -> Option<Point> {
+    let module_size = (axes[0].x.hypot(axes[0].y) + axes[1].x.hypot(axes[1].y)) / 2.0;
+    let max_run = (module_size * 2.0).ceil() as u32;
+    let range = |center: f64, size: u32| {
+        let low = (center - radius).floor().max(0.0) as i64;
+        let high = (center + radius).ceil().min(f64::from(size)) as i64;
+        low..high
+    };
+    let mut best: Option<Point> = None;
+    for y in range(estimate.y, matrix.height()) {
+        for x in range(estimate.x, matrix.width()) {
+            if matrix.get(x, y) != Some(true) {
+                continue;
+            }
+            let Some((offset, runs)) = cross_check(matrix, x, y, (1, 0), max_run) else {
+                continue;
+            };
+            if !is_alignment_ratio(&runs, module_size) {
+                continue;
+            }
+            let center_x = x as f64 + 0.5 + offset;
+            let column = center_x.floor() as i64;
+            let Some((offset, runs)) = cross_check(matrix, column, y, (0, 1), max_run) else {
+                continue;
+            };
+            if !is_alignment_ratio(&runs, module_size) {
+                continue;
+            }
+            let center = Point::new(center_x, y as f64 + 0.5 + offset);
+            if !matches_alignment_template(matrix, center, axes) {
+                continue;
+            }
+            if best.is_none_or(|b| center.distance(estimate) < b.distance(estimate)) {
+                best = Some(center);
+            }
+        }
+    }
+    best
+}
+
+/// The position of the module grid of a symbol in an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    pub version: Version,
+
+    /// Maps module coordinates to image coordinates. The centre of module
+    /// (x, y) has module coordinates (x + 0.5, y + 0.5).
+    pub transform: Perspective,
+
+    /// The centre of the bottom-right alignment pattern, if it was found.
+    pub alignment: Option<Point>,
+
+    /// The average module size, in pixels.
+    pub module_size: f64,
+}
+
+impl Grid {
+    /// Computes the grid of a symbol of the given version from its finder
+    /// patterns, refining it with the bottom-right alignment pattern.
+    pub fn locate(matrix: &BitMatrix, triple: &FinderTriple, version: Version) -> Self {
+        let width = version.width();
+        let far = f64::from(width) - 3.5;
+        let (tl, tr, bl) =
+            (triple.top_left.center, triple.top_right.center, triple.bottom_left.center);
+        let module_size = triple.module_size;
+
+        // Where the finders alone would put a point at module coordinates
+        // (x, y), assuming no perspective distortion.
+        let affine = |x: f64, y: f64| {
+            let (u, v) = ((x - 3.5) / (far - 3.5), (y - 3.5) / (far - 3.5));
+            Point::new(
+                tl.x + (tr.x - tl.x) * u + (bl.x - tl.x) * v,
+                tl.y + (tr.y - tl.y) * u + (bl.y - tl.y) * v,
+            )
+        };
+
+        let alignment_center = f64::from(width) - 6.5;
+        let alignment = match version {
+            Version::Normal(1) => None,
+            Version::Normal(_) => {
+                let estimate = affine(alignment_center, alignment_center);
+                let axes = [
+                    Point::new((tr.x - tl.x) / (far - 3.5), (tr.y - tl.y) / (far - 3.5)),
+                    Point::new((bl.x - tl.x) / (far - 3.5), (bl.y - tl.y) / (far - 3.5)),
+                ];
+                ALIGNMENT_SEARCH_RADII.iter().find_map(|radius| {
+                    find_alignment_pattern(matrix, estimate, axes, radius * module_size)
+                })
+            }
+        };
+        let (corner, corner_image) = match alignment {
+            Some(p) => (Point::new(alignment_center, alignment_center), p),
+            None => (Point::new(far, far), affine(far, far)),
+        };
+        let transform = Perspective::from_quads(
+            [Point::new(3.5, 3.5), Point::new(far, 3.5), corner, Point::new(3.5, far)],
+            [tl, tr, corner_image, bl],
+        );
+        Self {
+            version,
+            transform,
+            alignment,
+            module_size,
+        }
+    }
+
+    /// The number of modules per side.
+    pub fn width(&self) -> usize {
+        self.version.width().as_usize()
+    }
+
+    /// The outer corners of the symbol in the image, clockwise from the top
+    /// left, without quiet zone.
+    pub fn corners(&self) -> [Point; 4] {
+        let width = f64::from(self.version.width());
+        [(0.0, 0.0), (width, 0.0), (width, width), (0.0, width)]
+            .map(|(x, y)| self.transform.map(Point::new(x, y)))
+    }
+
+    /// Samples the centre of every module with the global transform. Modules
+    /// falling outside the image are light.
+    pub fn sample(&self, matrix: &BitMatrix) -> Vec<Color> {
+        let width = self.version.width();
+        (0..width)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| sample_at(matrix, self.transform.map(module_center(x, y))))
+            .collect()
+    }
+
+    /// Searches for every alignment pattern near the position the global
+    /// transform predicts for it. Returns the module coordinates of the
+    /// centre of each pattern, with its image position if it was found.
+    pub fn find_alignment_patterns(
+        &self,
+        matrix: &BitMatrix,
+    ) -> Vec<((i16, i16), Option<Point>)> {
+        alignment_pattern_centers(self.version)
+            .into_iter()
+            .map(|(x, y)| {
+                let center = module_center(x, y);
+                let predicted = self.transform.map(center);
+                let step = |dx: f64, dy: f64| {
+                    let p = self.transform.map(Point::new(center.x + dx, center.y + dy));
+                    Point::new(p.x - predicted.x, p.y - predicted.y)
+                };
+                let axes = [step(1.0, 0.0), step(0.0, 1.0)];
+                let radius = LOCAL_ALIGNMENT_SEARCH_RADIUS * self.module_size;
+                ((x, y), find_alignment_pattern(matrix, predicted, axes, radius))
+            })
+            .collect()
+    }
+
+    /// Samples the grid using a separate transform for each cell between
+    /// adjacent alignment patterns, which follows local distortions better
+    /// than `sample`. Symbols with at most one alignment pattern are sampled
+    /// exactly like `sample`.
+    pub fn sample_piecewise(&self, matrix: &BitMatrix) -> Vec<Color> {
+        let centers = alignment_pattern_centers(self.version);
+        if centers.len() <= 1 {
+            return self.sample(matrix);
+        }
+        let mut positions = centers.iter().map(|(x, _)| *x).collect::<Vec<_>>();
+        positions.push(6);
+        positions.sort_unstable();
+        positions.dedup();
+
+        // The image position of every grid point. The three points hidden
+        // under the finder patterns, and alignment patterns which cannot be
+        // found, are taken from the global transform.
+        let found = self.find_alignment_patterns(matrix);
+        let n = positions.len();
+        let mut points = Vec::with_capacity(n * n);
+        for &y in &positions {
+            for &x in &positions {
+                let point = found
+                    .iter()
+                    .find_map(|(center, point)| (*center == (x, y)).then_some(*point).flatten())
+                    .unwrap_or_else(|| self.transform.map(module_center(x, y)));
+                points.push(point);
+            }
+        }
+
+        let mut cells = Vec::with_capacity((n - 1) * (n - 1));
+        for j in 0..n - 1 {
+            for i in 0..n - 1 {
+                let corner = |di: usize, dj: usize| {
+                    (
+                        module_center(positions[i + di], positions[j + dj]),
+                        points[(j + dj) * n + i + di],
+                    )
+                };
+                let quad = [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)];
+                cells.push(Perspective::from_quads(quad.map(|c| c.0), quad.map(|c| c.1)));
+            }
+        }
+
+        // Modules outside the outermost alignment patterns use the nearest
+        // cell.
+        let cell_index = |v: i16| positions[1..n - 1].iter().filter(|p| **p <= v).count();
+        let width = self.version.width();
+        (0..width)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let cell = &cells[cell_index(y) * (n - 1) + cell_index(x)];
+                sample_at(matrix, cell.map(module_center(x, y)))
+            })
+            .collect()
+    }
+}
+
+fn sample_at(matrix: &BitMatrix, p: Point) -> Color {
+    match matrix.get(p.x.floor() as i64, p.y.floor() as i64) {
+        Some(true) => Color::Dark,
+        _ => Color::Light,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qr_code::QrCode;
+    use crate::scan::binarize::{Binarizer, Otsu};
+    use crate::scan::finder::{find_finder_patterns, find_triples};
+    use crate::scan::tests::{render, warp};
+    use crate::types::EcLevel;
+
+    #[test]
+    fn test_from_quads() {
+        let from = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let to = [(3.0, 7.0), (52.0, 11.0), (47.5, 60.0), (-2.0, 41.0)];
+        let [from, to] = [from, to].map(|quad| quad.map(|(x, y)| Point::new(x, y)));
+        let transform = Perspective::from_quads(from, to);
+        for (p, q) in from.into_iter().zip(to) {
+            assert!(transform.map(p).distance(q) < 1e-9);
+        }
+        let inverse = Perspective::from_quads(to, from);
+        let p = Point::new(4.0, 6.0);
+        assert!(inverse.map(transform.map(p)).distance(p) < 1e-9);
+    }
+
+    /// Renders `data`, warps it by a mild perspective (or only a shear for
+    /// symbols without alignment patterns, which are located by an affine
+    /// transform), locates the grid of the symbol and checks that both ways
+    /// of sampling it give back the modules.
+    fn check_sampling(data: &[u8], ec_level: EcLevel) -> (Grid, BitMatrix) {
+        let code = QrCode::with_error_correction_level(data, ec_level).unwrap();
+        let image = render(&code, 5);
+        let size = f64::from(image.width());
+        let bottom_right = match code.version() {
+            Version::Normal(1) => (size, size - 10.0),
+            Version::Normal(_) => (size + 35.0, size + 25.0),
+        };
+        let to = [(20.0, 30.0), (size + 15.0, 10.0), bottom_right, (5.0, size + 10.0)]
+            .map(|(x, y)| Point::new(x, y));
+        let matrix = Otsu.binarize(&warp(&image, to, image.width() + 40, image.height() + 40));
+        let triple = find_triples(&matrix, &find_finder_patterns(&matrix))[0];
+        assert_eq!(triple.estimated_version(), code.version());
+        let grid = Grid::locate(&matrix, &triple, code.version());
+        assert_eq!(grid.sample(&matrix), code.to_colors());
+        assert_eq!(grid.sample_piecewise(&matrix), code.to_colors());
+        (grid, matrix)
+    }
+
+    #[test]
+    fn test_sample_small() {
+        let (grid, _) = check_sampling(b"v1", EcLevel::L);
+        assert_eq!(grid.version, Version::Normal(1));
+        assert_eq!(grid.alignment, None);
+    }
+
+    #[test]
+    fn test_sample_with_alignment_patterns() {
+        let (grid, matrix) = check_sampling(&b"sampled piecewise".repeat(12), EcLevel::M);
+        assert!(matches!(grid.version, Version::Normal(7..)));
+        assert!(grid.alignment.is_some());
+        let found = grid.find_alignment_patterns(&matrix);
+        assert_eq!(found.len(), alignment_pattern_centers(grid.version).len());
+        for ((x, y), point) in found {
+            let point = point.expect("every alignment pattern is found");
+            let predicted = grid.transform.map(module_center(x, y));
+            assert!(point.distance(predicted) < grid.module_size);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Interactive terminal explorer.
+//!
+//! The symbol is re-encoded on every key press and drawn with half-block
+//! characters, two modules per terminal cell. A cursor can be moved over the
+//! modules to show their provenance.
+
+use std::io;
+
+use qr_code::bits;
+use qr_code::canvas::{Canvas, MaskPattern, Module, ALL_PATTERNS_QR};
+use qr_code::mask::IsoPenalty;
+use qr_code::optimize::Segment;
+use qr_code::pipeline::EncodedBits;
+use qr_code::provenance::ProvenanceMap;
+use qr_code::types::{Color, EcLevel, QrResult, Version};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color as TermColor, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+/// The number of light modules drawn around the symbol.
+const QUIET_ZONE: i16 = 2;
+
+/// The result of encoding the current input.
+struct Encoded {
+    version: Version,
+    segments: Vec<Segment>,
+    pattern: MaskPattern,
+    unmasked: Canvas,
+    masked: Canvas,
+    map: ProvenanceMap,
+}
+
+struct App {
+    input: String,
+    ec_level: EcLevel,
+    forced_mask: Option<MaskPattern>,
+    cursor: (i16, i16),
+    encoded: QrResult<Encoded>,
+}
+
+impl App {
+    fn new() -> Self {
+        let mut app = Self {
+            input: "HELLO WORLD".to_owned(),
+            ec_level: EcLevel::M,
+            forced_mask: None,
+            cursor: (0, 0),
+            encoded: Err(qr_code::types::QrError::DataTooLong),
+        };
+        app.encode();
+        app
+    }
+
+    fn encode(&mut self) {
+        let data = self.input.as_bytes();
+        self.encoded = bits::optimal_segments(data, self.ec_level).and_then(|(_, segments)| {
+            let placed = EncodedBits::new(data, self.ec_level)?.into_codewords()?.place();
+            let unmasked = placed.canvas().clone();
+            let symbol = match self.forced_mask {
+                Some(pattern) => placed.mask_with(pattern),
+                None => placed.mask(&IsoPenalty),
+            };
+            let version = unmasked.version();
+            Ok(Encoded {
+                version,
+                segments,
+                pattern: symbol.mask_pattern(),
+                unmasked,
+                masked: symbol.canvas().clone(),
+                map: ProvenanceMap::new(version, self.ec_level)?,
+            })
+        });
+        if let Ok(encoded) = &self.encoded {
+            let max = encoded.version.width() - 1;
+            self.cursor = (self.cursor.0.min(max), self.cursor.1.min(max));
+        }
+    }
+
+    /// Handles a key press. Returns false when the application should quit.
+    fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        match code {
+            KeyCode::Esc => return false,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+            // Other shortcuts are not part of the input.
+            KeyCode::Char(_)
+                if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {}
+            KeyCode::Char(c) => {
+                self.input.push(c);
+                self.encode();
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+                self.encode();
+            }
+            KeyCode::Tab => {
+                self.ec_level = match self.ec_level {
+                    EcLevel::L => EcLevel::M,
+                    EcLevel::M => EcLevel::Q,
+                    EcLevel::Q => EcLevel::H,
+                    EcLevel::H => EcLevel::L,
+                };
+                self.encode();
+            }
+            KeyCode::F(2) => {
+                self.forced_mask = match self.forced_mask {
+                    None => Some(ALL_PATTERNS_QR[0]),
+                    Some(p) => ALL_PATTERNS_QR.get(p as usize + 1).copied(),
+                };
+                self.encode();
+            }
+            KeyCode::Left => self.move_cursor(-1, 0),
+            KeyCode::Right => self.move_cursor(1, 0),
+            KeyCode::Up => self.move_cursor(0, -1),
+            KeyCode::Down => self.move_cursor(0, 1),
+            _ => {}
+        }
+        true
+    }
+
+    fn move_cursor(&mut self, dx: i16, dy: i16) {
+        if let Ok(encoded) = &self.encoded {
+            let max = encoded.version.width() - 1;
+            self.cursor = (
+                (self.cursor.0 + dx).clamp(0, max),
+                (self.cursor.1 + dy).clamp(0, max),
+            );
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [symbol_area, side] =
+            Layout::horizontal([Constraint::Min(0), Constraint::Length(48)]).areas(main);
+
+        frame.render_widget(
+            Paragraph::new("type to edit · Tab: EC level · F2: mask · arrows: cursor · Esc: quit"),
+            help,
+        );
+
+        let encoded = match &self.encoded {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                let text = format!("payload: {}\n\ncannot encode: {e:?}", self.input);
+                frame.render_widget(Paragraph::new(text).block(Block::bordered()), side);
+                return;
+            }
+        };
+
+        frame.render_widget(
+            Paragraph::new(self.symbol_lines(encoded)).block(Block::bordered().title("symbol")),
+            symbol_area,
+        );
+
+        let Version::Normal(version) = encoded.version;
+        let (x, y) = self.cursor;
+        let pre_mask = match encoded.unmasked.get(x, y) {
+            Module::Unmasked(c) => format!("{c:?}"),
+            Module::Empty => "Light (remainder)".to_owned(),
+            Module::Masked(_) => "not masked".to_owned(),
+        };
+        let mut lines = vec![
+            Line::from(format!("payload:  {}", self.input)),
+            Line::from(format!("version:  {version}")),
+            Line::from(format!("EC level: {:?}", self.ec_level)),
+            Line::from(format!(
+                "mask:     {} ({})",
+                encoded.pattern as u8,
+                if self.forced_mask.is_some() { "forced" } else { "auto" }
+            )),
+            Line::from(""),
+            Line::from("segments:"),
+        ];
+        lines.extend(encoded.segments.iter().map(|s| {
+            let text = String::from_utf8_lossy(&self.input.as_bytes()[s.begin..s.end]);
+            Line::from(format!("  {:?} {:?}", s.mode, text))
+        }));
+        lines.extend([
+            Line::from(""),
+            Line::from(format!("cursor:   ({x}, {y})")),
+            Line::from(format!("color:    {:?}", Color::from(encoded.masked.get(x, y)))),
+            Line::from(format!("role:     {}", encoded.map.get(x, y))),
+            Line::from(format!("pre-mask: {pre_mask}")),
+        ]);
+        frame.render_widget(
+            Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title("details")),
+            side,
+        );
+    }
+
+    /// Renders the symbol with `▀`, using the foreground for the upper module
+    /// and the background for the lower one.
+    fn symbol_lines(&self, encoded: &Encoded) -> Vec<Line<'static>> {
+        let width = encoded.version.width();
+        let color_at = |x: i16, y: i16| {
+            if !(0..width).contains(&x) || !(0..width).contains(&y) {
+                TermColor::White
+            } else if (x, y) == self.cursor {
+                TermColor::Red
+            } else {
+                Color::from(encoded.masked.get(x, y)).select(TermColor::Black, TermColor::White)
+            }
+        };
+        (-QUIET_ZONE..width + QUIET_ZONE)
+            .step_by(2)
+            .map(|y| {
+                (-QUIET_ZONE..width + QUIET_ZONE)
+                    .map(|x| {
+                        let style = Style::default().fg(color_at(x, y)).bg(color_at(x, y + 1));
+                        Span::styled("▀", style)
+                    })
+                    .collect::<Line>()
+            })
+            .collect()
+    }
+}
+
+/// Runs the explorer until the user quits.
+pub fn run() -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal) -> io::Result<()> {
+    let mut app = App::new();
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && !app.on_key(key.code, key.modifiers)
+        {
+            return Ok(());
+        }
+    }
+}
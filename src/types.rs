@@ -31,6 +31,44 @@ pub enum QrError {
 
     /// A character not belonging to the character set is found.
     InvalidCharacter,
+
+    /// The data codewords do not fill the capacity of the version, e.g.
+    /// because the bit stream was not terminated and padded.
+    InvalidDataLength,
+
+    /// A block contains more errors than its error correction codewords can
+    /// recover.
+    TooManyErrors,
+
+    /// Neither copy of the format information could be recovered.
+    InvalidFormatInfo,
+
+    /// The decoded bit stream does not follow the QR code syntax.
+    InvalidBitstream,
+
+    /// Decoding a freshly encoded symbol did not give back what it was built
+    /// from. Errors while decoding it are returned as they are.
+    VerificationFailed(Mismatch),
+}
+
+/// What differs between a freshly encoded symbol and the result of decoding
+/// it, see `QrCode::verify`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Mismatch {
+    /// The payload or the data codewords.
+    Data,
+
+    Version,
+
+    EcLevel,
+
+    MaskPattern,
+
+    /// Decoding needed error correction.
+    Corrections,
+
+    /// The symbol only decoded mirror-imaged.
+    Mirrored,
 }
 
 
@@ -77,8 +115,8 @@ impl Mode{
 
     pub const fn data_bits_count(self, raw_data_len: usize) -> usize {
         match self {
-            Self::Numeric => (raw_data_len * 10 + 2) / 3,
-            Self::Alphanumeric => (raw_data_len * 11 + 1) / 2,
+            Self::Numeric => (raw_data_len * 10).div_ceil(3),
+            Self::Alphanumeric => (raw_data_len * 11).div_ceil(2),
             Self::Byte => raw_data_len * 8,
             Self::Kanji => raw_data_len * 13,
         }
@@ -123,11 +161,8 @@ impl Version {
     where
         T: PartialEq + Default + Copy,
     {
-        match self {
-            Self::Normal(v @ 1..=40) => {
-                return Ok(table[(v - 1).as_usize()][ec_level as usize]);
-            }
-            _ => {}
+        if let Self::Normal(v @ 1..=40) = self {
+            return Ok(table[(v - 1).as_usize()][ec_level as usize]);
         }
         Err(QrError::InvalidVersion)
     }
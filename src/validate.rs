@@ -0,0 +1,309 @@
+//! Checking that a module matrix is a well-formed symbol.
+//!
+//! Unlike `decode`, which tolerates as much damage as it can, the validator
+//! reports every deviation from the layout which
+//! `Canvas::draw_all_functional_patterns` produces for the version implied by
+//! the width: wrong function pattern modules, format and version information
+//! which is not an exact BCH codeword or disagrees with itself or the width,
+//! and a missing dark module. The data region is not checked.
+
+use std::fmt;
+
+use crate::bch::{self, FormatInfo};
+use crate::canvas::Canvas;
+use crate::cast::As;
+use crate::provenance::{ModuleRole, ProvenanceMap};
+use crate::types::{Color, EcLevel, Version};
+
+/// How much a finding matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Readers cope with it, but the symbol does not follow the standard.
+    Warning,
+
+    /// Readers are likely to fail.
+    Error,
+}
+
+/// A problem found in a matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// The matrix is not square, or no version has its width. Nothing else
+    /// is checked.
+    InvalidWidth { width: usize, len: usize },
+
+    /// Modules of a finder, separator, timing or alignment pattern have the
+    /// wrong color.
+    WrongPatternModules {
+        role: ModuleRole,
+        modules: Vec<(i16, i16)>,
+    },
+
+    /// A copy of the format information is not a valid codeword. `copy` is 0
+    /// for the one around the top-left finder pattern and 1 for the one split
+    /// between the other two, and `distance` is the number of wrong bits.
+    InvalidFormatInfo { copy: usize, distance: u32 },
+
+    /// The two copies of the format information decode to different values.
+    FormatInfoMismatch { main: FormatInfo, side: FormatInfo },
+
+    /// A copy of the version information is not a valid codeword. `copy` is
+    /// 0 for the bottom-left one and 1 for the top-right one.
+    InvalidVersionInfo { copy: usize, distance: u32 },
+
+    /// A copy of the version information decodes to a version of another
+    /// width.
+    VersionInfoMismatch { copy: usize, found: Version },
+
+    /// The module at (8, width - 8) is light.
+    MissingDarkModule,
+}
+
+impl Finding {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::InvalidFormatInfo { distance, .. } | Self::InvalidVersionInfo { distance, .. }
+                if *distance <= bch::MAX_CORRECTABLE_DISTANCE =>
+            {
+                Severity::Warning
+            }
+            Self::MissingDarkModule => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidWidth { width, len } => {
+                write!(f, "{len} modules do not form a symbol of width {width}")
+            }
+            Self::WrongPatternModules { role, modules } => {
+                let (x, y) = modules[0];
+                write!(f, "{} {role} modules are wrong, first at ({x}, {y})", modules.len())
+            }
+            Self::InvalidFormatInfo { copy, distance } => {
+                write!(f, "format information copy {copy} has {distance} wrong bits")
+            }
+            Self::FormatInfoMismatch { main, side } => write!(
+                f,
+                "format information copies disagree: {:?} {:?} and {:?} {:?}",
+                main.ec_level, main.mask_pattern, side.ec_level, side.mask_pattern,
+            ),
+            Self::InvalidVersionInfo { copy, distance } => {
+                write!(f, "version information copy {copy} has {distance} wrong bits")
+            }
+            Self::VersionInfoMismatch { copy, found } => {
+                write!(f, "version information copy {copy} says {found:?}, unlike the width")
+            }
+            Self::MissingDarkModule => f.write_str("the dark module is light"),
+        }
+    }
+}
+
+/// Checks the structure of a symbol given as `width × width` colors in
+/// left-to-right, then top-to-bottom order, without quiet zone. Returns an
+/// empty list if it is well-formed.
+pub fn validate(colors: &[Color], width: usize) -> Vec<Finding> {
+    if !(21..=177).contains(&width)
+        || !(width - 17).is_multiple_of(4)
+        || colors.len() != width * width
+    {
+        return vec![Finding::InvalidWidth {
+            width,
+            len: colors.len(),
+        }];
+    }
+    let version = Version::Normal(((width - 17) / 4).as_i16());
+    let mut findings = Vec::new();
+
+    // The function patterns do not depend on the EC level.
+    let mut expected = Canvas::new(version, EcLevel::L);
+    expected.draw_all_functional_patterns();
+    let map = ProvenanceMap::new(version, EcLevel::L).expect("valid version");
+    let w = version.width();
+    for role in [
+        ModuleRole::Finder,
+        ModuleRole::Separator,
+        ModuleRole::Timing,
+        ModuleRole::Alignment,
+    ] {
+        let modules = (0..w)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .filter(|(x, y)| {
+                map.get(*x, *y) == role
+                    && colors[(y * w + x).as_usize()] != Color::from(expected.get(*x, *y))
+            })
+            .collect::<Vec<_>>();
+        if !modules.is_empty() {
+            findings.push(Finding::WrongPatternModules { role, modules });
+        }
+    }
+
+    let copies = bch::read_format_info_copies(colors, width);
+    for (copy, info) in copies.iter().enumerate() {
+        if info.distance > 0 {
+            findings.push(Finding::InvalidFormatInfo {
+                copy,
+                distance: info.distance,
+            });
+        }
+    }
+    let [main, side] = copies;
+    if main.is_reliable() && side.is_reliable() && main.value != side.value {
+        findings.push(Finding::FormatInfoMismatch {
+            main: main.value,
+            side: side.value,
+        });
+    }
+
+    if matches!(version, Version::Normal(7..)) {
+        for (copy, info) in bch::read_version_info_copies(colors, width).iter().enumerate() {
+            if info.distance > 0 {
+                findings.push(Finding::InvalidVersionInfo {
+                    copy,
+                    distance: info.distance,
+                });
+            }
+            if info.is_reliable() && info.value != version {
+                findings.push(Finding::VersionInfoMismatch {
+                    copy,
+                    found: info.value,
+                });
+            }
+        }
+    }
+
+    if colors[((w - 8) * w + 8).as_usize()] != Color::Dark {
+        findings.push(Finding::MissingDarkModule);
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::{
+        MaskPattern, FORMAT_INFO_COORDS_QR_MAIN, FORMAT_INFO_COORDS_QR_SIDE,
+        VERSION_INFO_COORDS_BL,
+    };
+    use crate::bits::Bits;
+    use crate::qr_code::QrCode;
+
+    /// A symbol of the given version at level Q, its colors and its width.
+    fn symbol(version: i16) -> (QrCode, Vec<Color>, usize) {
+        let mut bits = Bits::new(Version::Normal(version));
+        bits.push_byte_data(b"validate").unwrap();
+        bits.push_terminator(EcLevel::Q).unwrap();
+        let code = QrCode::with_bits(bits, EcLevel::Q).unwrap();
+        let colors = code.to_colors();
+        let width = code.width();
+        (code, colors, width)
+    }
+
+    /// The index of the module at `(x, y)`, where negative coordinates count
+    /// from the right or bottom edge.
+    fn index(width: usize, (x, y): (i16, i16)) -> usize {
+        let w = width.as_i16();
+        let wrap = |c: i16| if c < 0 { c + w } else { c };
+        (wrap(y) * w + wrap(x)).as_usize()
+    }
+
+    fn flip(colors: &mut [Color], width: usize, coord: (i16, i16)) {
+        let i = index(width, coord);
+        colors[i] = !colors[i];
+    }
+
+    /// Writes `value` to the modules at `coords`, most significant bit first,
+    /// as `bch` reads it.
+    fn write_number(colors: &mut [Color], width: usize, coords: &[(i16, i16)], value: u32) {
+        for (i, coord) in coords.iter().enumerate() {
+            let dark = value >> (coords.len() - 1 - i) & 1 == 1;
+            colors[index(width, *coord)] = if dark { Color::Dark } else { Color::Light };
+        }
+    }
+
+    #[test]
+    fn test_clean_symbols() {
+        for version in [1, 5, 14] {
+            let (_, colors, width) = symbol(version);
+            assert_eq!(validate(&colors, width), []);
+        }
+    }
+
+    #[test]
+    fn test_invalid_width() {
+        let findings = validate(&[Color::Light; 22 * 22], 22);
+        assert_eq!(findings, [Finding::InvalidWidth { width: 22, len: 22 * 22 }]);
+        assert_eq!(findings[0].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn test_wrong_timing_module() {
+        let (_, mut colors, width) = symbol(1);
+        flip(&mut colors, width, (10, 6));
+        let findings = validate(&colors, width);
+        assert_eq!(
+            findings,
+            [Finding::WrongPatternModules {
+                role: ModuleRole::Timing,
+                modules: vec![(10, 6)],
+            }]
+        );
+        assert_eq!(findings[0].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn test_corrupted_format_copy() {
+        let (_, mut colors, width) = symbol(1);
+        flip(&mut colors, width, FORMAT_INFO_COORDS_QR_MAIN[3]);
+        let findings = validate(&colors, width);
+        assert_eq!(findings, [Finding::InvalidFormatInfo { copy: 0, distance: 1 }]);
+        assert_eq!(findings[0].severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn test_mismatched_format_copies() {
+        let (code, mut colors, width) = symbol(5);
+        let main = bch::read_format_info_copies(&colors, width)[0].value;
+        assert_eq!(main.ec_level, code.error_correction_level());
+        let side = FormatInfo {
+            ec_level: EcLevel::L,
+            mask_pattern: MaskPattern::Diamonds,
+        };
+        assert_ne!(main, side);
+        let word = u32::from(bch::encode_format_info(side));
+        write_number(&mut colors, width, &FORMAT_INFO_COORDS_QR_SIDE, word);
+        assert_eq!(validate(&colors, width), [Finding::FormatInfoMismatch { main, side }]);
+    }
+
+    #[test]
+    fn test_bad_version_info() {
+        let (_, mut colors, width) = symbol(14);
+        flip(&mut colors, width, VERSION_INFO_COORDS_BL[5]);
+        let findings = validate(&colors, width);
+        assert_eq!(findings, [Finding::InvalidVersionInfo { copy: 0, distance: 1 }]);
+        assert_eq!(findings[0].severity(), Severity::Warning);
+
+        let (_, mut colors, width) = symbol(14);
+        let word = bch::encode_version_info(Version::Normal(9)).unwrap();
+        write_number(&mut colors, width, &VERSION_INFO_COORDS_BL, word);
+        assert_eq!(
+            validate(&colors, width),
+            [Finding::VersionInfoMismatch {
+                copy: 0,
+                found: Version::Normal(9),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_missing_dark_module() {
+        let (_, mut colors, width) = symbol(1);
+        flip(&mut colors, width, (8, -8));
+        let findings = validate(&colors, width);
+        assert_eq!(findings, [Finding::MissingDarkModule]);
+        assert_eq!(findings[0].severity(), Severity::Warning);
+    }
+}
@@ -0,0 +1,151 @@
+//! A numbered series of images showing how a symbol is built, step by step.
+//!
+//! Every frame is colour-coded by region, using `ProvenanceMap` to decide
+//! which region a module belongs to. Dark modules use a saturated colour and
+//! light modules a pale tint of it; modules which are still empty are grey.
+
+use std::path::{Path, PathBuf};
+
+use image::{ImageResult, Rgb, RgbImage};
+
+use crate::canvas::{Canvas, Module, ALL_PATTERNS_QR};
+use crate::cast::As;
+use crate::pipeline::EncodedBits;
+use crate::provenance::{CodewordKind, ModuleRole, ProvenanceMap};
+use crate::render::Renderer;
+use crate::types::{Color, EcLevel, QrResult};
+
+/// The number of frames used to show the data placement.
+const DATA_FRAMES: usize = 16;
+
+/// A single image of the walkthrough.
+pub struct Frame {
+    /// A short file-name friendly description, e.g. `finder-patterns`.
+    pub label: String,
+    pub image: RgbImage,
+}
+
+/// Returns the colour of a module of the given role in walkthrough images.
+pub fn region_color(role: ModuleRole, color: Color) -> Rgb<u8> {
+    let (dark, light) = match role {
+        ModuleRole::Finder => ([180, 30, 30], [250, 215, 215]),
+        ModuleRole::Separator => ([180, 30, 30], [255, 240, 240]),
+        ModuleRole::Timing => ([30, 140, 60], [210, 245, 215]),
+        ModuleRole::Alignment => ([210, 110, 0], [255, 225, 190]),
+        ModuleRole::FormatInfo => ([30, 80, 200], [205, 220, 255]),
+        ModuleRole::VersionInfo => ([120, 40, 170], [230, 210, 245]),
+        ModuleRole::DarkModule => ([0, 0, 0], [255, 255, 255]),
+        ModuleRole::Codeword {
+            kind: CodewordKind::Data,
+            ..
+        } => ([20, 20, 20], [245, 245, 245]),
+        ModuleRole::Codeword {
+            kind: CodewordKind::ErrorCorrection,
+            ..
+        } => ([0, 110, 120], [210, 240, 240]),
+        ModuleRole::Remainder => ([110, 110, 110], [225, 225, 225]),
+    };
+    Rgb(color.select(dark, light))
+}
+
+/// Builds all frames of the walkthrough for the given data.
+pub fn frames(data: &[u8], ec_level: EcLevel) -> QrResult<Vec<Frame>> {
+    let codewords = EncodedBits::new(data, ec_level)?.into_codewords()?;
+    let version = codewords.version();
+    let map = ProvenanceMap::new(version, ec_level)?;
+    let mut frames = Vec::new();
+    let mut push = |label: String, canvas: &Canvas| {
+        frames.push(Frame {
+            label,
+            image: render_regions(canvas, &map),
+        });
+    };
+
+    let mut canvas = Canvas::new(version, ec_level);
+    push("blank".to_owned(), &canvas);
+    canvas.draw_finder_patterns();
+    push("finder-patterns".to_owned(), &canvas);
+    canvas.draw_alignment_patterns();
+    push("alignment-patterns".to_owned(), &canvas);
+    canvas.draw_timing_patterns();
+    push("timing-patterns".to_owned(), &canvas);
+    canvas.draw_reserved_format_info_patterns();
+    push("reserved-format-info".to_owned(), &canvas);
+    canvas.draw_version_info_patterns();
+    push("version-info".to_owned(), &canvas);
+
+    // Place the codewords a few at a time, following the zigzag path.
+    let data = codewords.interleaved_data();
+    let ec = codewords.interleaved_ec();
+    let total = data.len() + ec.len();
+    let step = total.div_ceil(DATA_FRAMES).max(1);
+    let mut placed = step.min(total);
+    loop {
+        let mut c = canvas.clone();
+        if placed <= data.len() {
+            c.draw_data(&data[..placed], &[]);
+        } else {
+            c.draw_data(&data, &ec[..placed - data.len()]);
+        }
+        push(format!("codewords-{placed:04}-of-{total:04}"), &c);
+        if placed == total {
+            canvas = c;
+            break;
+        }
+        placed = (placed + step).min(total);
+    }
+
+    let candidates = ALL_PATTERNS_QR
+        .iter()
+        .map(|pattern| {
+            let mut c = canvas.clone();
+            c.apply_mask(*pattern);
+            (*pattern, c.compute_total_penalty_scores(), c)
+        })
+        .collect::<Vec<_>>();
+    for (pattern, penalty, c) in &candidates {
+        push(format!("mask-{}-penalty-{penalty}", *pattern as u8), c);
+    }
+
+    let (pattern, _, best) = candidates
+        .into_iter()
+        .min_by_key(|(_, penalty, _)| *penalty)
+        .expect("at least one pattern");
+    frames.push(Frame {
+        label: format!("final-mask-{}", pattern as u8),
+        image: render_plain(best),
+    });
+    Ok(frames)
+}
+
+/// Saves the frames as `NN-label.png` in `dir`, returning the written paths.
+pub fn write_frames(frames: &[Frame], dir: &Path) -> ImageResult<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let path = dir.join(format!("{i:02}-{}.png", frame.label));
+            frame.image.save(&path)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+fn render_regions(canvas: &Canvas, map: &ProvenanceMap) -> RgbImage {
+    let width = canvas.width().as_usize();
+    let colors = canvas.clone().into_colors();
+    Renderer::<Rgb<u8>>::new(&colors, width, 4).build_with(|i, color| {
+        let (x, y) = ((i % width).as_i16(), (i / width).as_i16());
+        match canvas.get(x, y) {
+            Module::Empty => Rgb([190, 190, 190]),
+            _ => region_color(map.get(x, y), color),
+        }
+    })
+}
+
+fn render_plain(canvas: Canvas) -> RgbImage {
+    let width = canvas.width().as_usize();
+    let colors = canvas.into_colors();
+    Renderer::<Rgb<u8>>::new(&colors, width, 4).build()
+}